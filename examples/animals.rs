@@ -12,24 +12,24 @@ pub struct Named(String);
 fn main() {
     let world = LockedWorld::new();
 
-    let start_work = Work::new().add_system(|world: &LockedWorld| {
+    let mut animals = vec![
+        ("the armed one", false, true),
+        ("the legged one", true, false),
+        ("the legged and armed one", true, true),
+    ];
+    let start_work = Work::new().add_system(move |world: &LockedWorld| {
         let mut world = world.lock_exclusive();
 
-        let armed_entity = world.create_entity();
-        world.insert(&armed_entity, Armed);
-        world.insert(&armed_entity, Named(String::from("the armed one")));
-
-        let legged_entity = world.create_entity();
-        world.insert(&legged_entity, Legged);
-        world.insert(&legged_entity, Named(String::from("the legged one")));
-
-        let armed_and_legged_entity = world.create_entity();
-        world.insert(&armed_and_legged_entity, Legged);
-        world.insert(&armed_and_legged_entity, Armed);
-        world.insert(
-            &armed_and_legged_entity,
-            Named(String::from("the legged and armed one")),
-        );
+        for (name, legged, armed) in animals.drain(..) {
+            let entity = world.create_entity();
+            if legged {
+                world.insert(&entity, Legged);
+            }
+            if armed {
+                world.insert(&entity, Armed);
+            }
+            world.insert(&entity, Named(String::from(name)));
+        }
     });
 
     let print_work = Work::new().add_system(|world: &LockedWorld| {
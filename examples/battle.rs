@@ -1,15 +1,24 @@
-use retaker::{work::Work, world::LockedWorld};
+use retaker::{
+    commands::Commands,
+    query::Query,
+    schedule::{Schedule, Stage, SystemConfig},
+    system::ResMut,
+    time::Runner,
+    work::resource_equals,
+    world::LockedWorld,
+};
 
 pub struct Health(i32);
 pub struct Attaker {
     damage: u32,
 }
-#[derive(PartialEq, Eq, Debug)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
 pub enum Faction {
     Allies,
     Enemies,
 }
 pub struct Dead;
+#[derive(PartialEq)]
 pub struct Battle {
     on_going: bool,
 }
@@ -19,74 +28,58 @@ fn create_battle(world: &LockedWorld) {
     _ = world.create_resource(Battle { on_going: true });
 }
 
-fn create_player(world: &LockedWorld) {
-    let mut world = world.lock_exclusive();
-    let player = world.create_entity();
-    world.insert(&player, Health(3));
-    world.insert(&player, Attaker { damage: 1 });
-    world.insert(&player, Faction::Allies);
+fn create_player(mut commands: Commands) {
+    commands.spawn_bundle((Health(3), Attaker { damage: 1 }, Faction::Allies));
 }
 
-fn create_enemy(world: &LockedWorld) {
-    let mut world = world.lock_exclusive();
-    let enemy = world.create_entity();
-    world.insert(&enemy, Health(2));
-    world.insert(&enemy, Attaker { damage: 1 });
-    world.insert(&enemy, Faction::Enemies);
+fn create_enemy(mut commands: Commands) {
+    commands.spawn_bundle((Health(2), Attaker { damage: 1 }, Faction::Enemies));
 }
 
-fn tick_attacks(world: &LockedWorld) {
-    let world = world.lock_shared();
-
-    let battle_state = world.resource::<Battle>().unwrap();
-    if !battle_state.on_going {
-        return;
-    }
-
-    let attaker_comps = world.components::<Attaker>().unwrap();
-    let mut health_comps = world.components_mut::<Health>().unwrap();
-    let faction_comps = world.components::<Faction>().unwrap();
-
-    let attakers = attaker_comps.with(faction_comps.query());
-    let attackable = health_comps.with(faction_comps.query());
-
-    for attacker_id in attakers {
-        let attacker_faction = faction_comps.get(&attacker_id).unwrap();
-        let attacker_comp = attaker_comps.get(&attacker_id).unwrap();
-        for attackable_id in attackable.clone() {
-            let attackable_health = health_comps.get_mut(&attackable_id).unwrap();
-            let attackable_faction = faction_comps.get(&attackable_id).unwrap();
+fn tick_attacks(
+    attackers: Query<&Attaker>,
+    factions: Query<&Faction>,
+    mut healths: Query<&mut Health>,
+) {
+    let attackers = attackers
+        .iter()
+        .filter_map(|(id, attacker)| {
+            let (_, faction) = factions.get(&id)?;
+            Some((id, attacker.damage, *faction))
+        })
+        .collect::<Vec<_>>();
+
+    for (attacker_id, damage, attacker_faction) in attackers {
+        for (attackable_id, attackable_health) in healths.iter_mut() {
             if attackable_id == attacker_id {
                 continue;
             }
+            let attackable_faction = match factions.get(&attackable_id) {
+                Some((_, faction)) => *faction,
+                None => continue,
+            };
 
-            if *attackable_faction != *attacker_faction {
-                attackable_health.0 -= attacker_comp.damage as i32;
+            if attackable_faction != attacker_faction {
+                attackable_health.0 -= damage as i32;
                 println!(
                     "{:?} attacked {:?} and inflicted {}, {} health remaining on {:?} entity",
-                    *attacker_faction,
-                    *attackable_faction,
-                    attacker_comp.damage,
+                    attacker_faction,
+                    attackable_faction,
+                    damage,
                     attackable_health.0,
-                    *attackable_faction,
+                    attackable_faction,
                 );
-                continue;
             }
         }
     }
 }
 
-fn check_battle(world: &LockedWorld) {
-    let world = world.lock_shared();
-    let mut battle = world.resource_mut::<Battle>().unwrap();
+fn check_battle(mut battle: ResMut<Battle>, factions: Query<&Faction>) {
     if battle.on_going {
-        let factions = world.components::<Faction>().unwrap();
-        let reference_point = factions.query().into_iter().last();
-        if let Some(reference_point) = reference_point {
-            for faction in factions.query() {
-                if reference_point != faction {
-                    return;
-                }
+        let mut factions = factions.iter().map(|(_, faction)| *faction);
+        if let Some(reference_point) = factions.next() {
+            if factions.any(|faction| faction != reference_point) {
+                return;
             }
             battle.on_going = false;
             println!("battle finished");
@@ -94,55 +87,50 @@ fn check_battle(world: &LockedWorld) {
     }
 }
 
-fn print_dead(world: &LockedWorld) {
-    let world = world.lock_shared();
-    let healths = world.components::<Health>().unwrap();
-    let factions = world.components::<Faction>().unwrap();
-    for printable_id in factions.with(healths.query()) {
-        let faction = factions.get(&printable_id).unwrap();
-        let health = healths.get(&printable_id).unwrap();
+fn print_dead(dead: Query<(&Health, &Faction)>) {
+    for (_, health, faction) in dead.iter() {
         if health.0 <= 0 {
             println!("{:?} died", *faction);
         }
     }
 }
 
-fn clear_dead(world: &LockedWorld) {
-    let mut world = world.lock_exclusive();
-    let health_comps = world.components_mut::<Health>().unwrap();
-    let mut queued_deletes = vec![];
-    for health_id in health_comps.query() {
-        let health = health_comps.get(&health_id).unwrap();
+fn clear_dead(mut commands: Commands, healths: Query<&Health>) {
+    for (id, health) in healths.iter() {
         if health.0 <= 0 {
-            queued_deletes.push(health_id.clone());
+            commands.despawn(&id);
         }
     }
-    drop(health_comps);
-    for queued in queued_deletes {
-        world.delete_entity(&queued);
-    }
 }
 
 fn main() {
-    let world = LockedWorld::new();
-
-    let start_work = Work::new()
-        .add_system(create_battle)
-        .add_system(create_player)
-        .add_system(create_enemy);
-
-    let update_work = Work::new()
-        .add_system(check_battle)
-        .add_system(tick_attacks)
-        .add_system(print_dead)
-        .add_system(clear_dead);
-
-    start_work.run(&world);
+    let mut world = LockedWorld::new();
+
+    let schedule = Schedule::new()
+        .add_system(Stage::Startup, SystemConfig::new(create_battle))
+        .add_system(Stage::Startup, SystemConfig::new(create_player))
+        .add_system(Stage::Startup, SystemConfig::new(create_enemy))
+        .add_system(Stage::PreUpdate, SystemConfig::new(check_battle))
+        .add_system(
+            Stage::Update,
+            SystemConfig::new(tick_attacks).run_if(resource_equals(Battle { on_going: true })),
+        )
+        .add_system(
+            Stage::PostUpdate,
+            SystemConfig::new(print_dead).label("print dead"),
+        )
+        .add_system(
+            Stage::PostUpdate,
+            SystemConfig::new(clear_dead).after("print dead"),
+        )
+        .sync_point(LockedWorld::apply_commands);
+
+    let mut runner = Runner::new().add_variable(schedule);
 
     loop {
-        update_work.run(&world);
+        runner.tick(&mut world);
         let world = world.lock_shared();
-        let battle = world.resource::<Battle>().unwrap();
+        let battle = world.try_resource::<Battle>().unwrap();
         if !battle.on_going {
             break;
         }
@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use macroquad::{
     color::WHITE,
     input::{is_key_down, is_key_released, KeyCode},
@@ -8,6 +10,8 @@ use macroquad::{
     window::{next_frame, screen_height},
 };
 use retaker::{
+    event::EventCursor,
+    time::{FixedTime, Runner},
     work::{ThreadedWork, Work},
     world::{LockedWorld, World},
 };
@@ -16,17 +20,21 @@ pub struct Camera {
     position: Vec2,
     scale: f32,
     follow_average: bool,
-    hide_ui: bool,
+}
+
+/// Lives with macroquad on the main thread, so it's a non-send resource.
+pub struct UiState {
+    hidden: bool,
 }
 
 pub struct ParticleParameters {
-    delta_time: f32,
     particle_mass: f32,
-    reset: bool,
     particle_count: f32,
     velocity_variation: f32,
 }
 
+pub struct ResetParticles;
+
 pub struct Particle {
     position: Vec2,
     velocity: Vec2,
@@ -39,7 +47,6 @@ fn start_camera(world: &LockedWorld) {
         position: Vec2::ZERO,
         scale: 1.0,
         follow_average: false,
-        hide_ui: false
     });
 }
 
@@ -47,43 +54,40 @@ fn start_particles(world: &LockedWorld) {
     let mut world = world.lock_exclusive();
 
     world.create_resource(ParticleParameters {
-        delta_time: 1.0 / 30.0,
         particle_mass: 1.0,
-        reset: true,
         particle_count: 100.0,
         velocity_variation: 0.0,
     });
+    world.add_event::<ResetParticles>();
+    world.create_resource(EventCursor::<ResetParticles>::new());
 
     reset_particles(&mut world);
 }
 
 fn reset_particles(world: &mut World) {
-    if let Some(mut particles) = world.components_mut::<Particle>() {
-        particles.clear();
-    }
-    let mut parameters = world.resource_mut::<ParticleParameters>().unwrap();
-    parameters.reset = false;
+    let particles = world
+        .components::<Particle>()
+        .map_or(vec![], |particles| particles.query().into_iter().collect());
+    world.despawn_batch(&particles);
+    let parameters = world.resource::<ParticleParameters>().unwrap();
     let particle_count = parameters.particle_count;
     let velocity_variation = parameters.velocity_variation;
     drop(parameters);
-    for _ in 0..particle_count as usize {
-        let particle = world.create_entity();
-        world.insert(
-            &particle,
-            Particle {
-                position: vec2(gen_range(0.0, 600.0), gen_range(0.0, 600.0)),
-                velocity: vec2(
-                    gen_range(-velocity_variation, velocity_variation),
-                    gen_range(-velocity_variation, velocity_variation),
-                ),
-            },
-        );
-    }
+    world.spawn_batch((0..particle_count as usize).map(|_| {
+        (Particle {
+            position: vec2(gen_range(0.0, 600.0), gen_range(0.0, 600.0)),
+            velocity: vec2(
+                gen_range(-velocity_variation, velocity_variation),
+                gen_range(-velocity_variation, velocity_variation),
+            ),
+        },)
+    }));
 }
 
 fn update_particles(world: &LockedWorld) {
     let world = world.lock_shared();
     let parameters = world.resource::<ParticleParameters>().unwrap();
+    let delta_time = world.resource::<FixedTime>().unwrap().delta_seconds();
     let mut entities = world.components_mut::<Particle>().unwrap();
     let query = entities.query();
     for a_id in query.clone() {
@@ -94,29 +98,30 @@ fn update_particles(world: &LockedWorld) {
             let [a, b] = entities.get_many_mut([&a_id, &b_id]).unwrap();
 
             let difference = (b.position - a.position) * parameters.particle_mass;
-            a.velocity += difference * parameters.delta_time;
-            b.velocity -= difference * parameters.delta_time;
+            a.velocity += difference * delta_time;
+            b.velocity -= difference * delta_time;
         }
     }
-    for id in query {
-        let particle = entities.get_mut(&id).unwrap();
-        particle.position += particle.velocity * parameters.delta_time;
+    for (_, particle) in entities.iter_mut() {
+        particle.position += particle.velocity * delta_time;
     }
 }
 
 fn update_ui(world: &LockedWorld) {
     let world = world.lock_shared();
-    let mut camera = world.resource_mut::<Camera>().unwrap();
-    if camera.hide_ui {
+    if world.non_send_resource::<UiState>().unwrap().hidden {
         return;
     }
+    let mut camera = world.resource_mut::<Camera>().unwrap();
     let mut parameters = world.resource_mut::<ParticleParameters>().unwrap();
     macroquad::ui::root_ui().window(
         1,
         vec2(0.0, screen_height() - 160.0),
         vec2(360.0, 160.0),
         |ui| {
-            parameters.reset = ui.button(vec2(0.0, 140.0), "reset");
+            if ui.button(vec2(0.0, 140.0), "reset") {
+                world.send_event(ResetParticles);
+            }
             ui.slider(
                 1,
                 "particle mass",
@@ -129,15 +134,14 @@ fn update_ui(world: &LockedWorld) {
                 3.0..1000.0,
                 &mut parameters.particle_count,
             );
-            ui.slider(3, "delta time", 0.001..1.0, &mut parameters.delta_time);
-            ui.slider(4, "camera scale", 0.01..5.0, &mut camera.scale);
+            ui.slider(3, "camera scale", 0.01..5.0, &mut camera.scale);
             ui.slider(
-                5,
+                4,
                 "velocity variation",
                 0.0..1000.0,
                 &mut parameters.velocity_variation,
             );
-            ui.checkbox(6, "camera follow", &mut camera.follow_average);
+            ui.checkbox(5, "camera follow", &mut camera.follow_average);
         },
     );
 }
@@ -146,14 +150,14 @@ fn update_camera(world: &LockedWorld) {
     let world = world.lock_shared();
     let mut camera = world.resource_mut::<Camera>().unwrap();
     if is_key_released(KeyCode::Z) {
-        camera.hide_ui = if camera.hide_ui {false} else {true};
+        let mut ui_state = world.non_send_resource_mut::<UiState>().unwrap();
+        ui_state.hidden = !ui_state.hidden;
     }
     if camera.follow_average {
         let entities = world.components::<Particle>().unwrap();
         let mut average = Vec2::ZERO;
         let mut particle_count = 0.;
-        for id in entities.query() {
-            let particle = entities.get(&id).unwrap();
+        for (_, particle) in entities.iter() {
             average += particle.position;
             particle_count += 1.;
         }
@@ -177,10 +181,10 @@ fn update_camera(world: &LockedWorld) {
 
 fn check_reset(world: &LockedWorld) {
     let world = world.lock_upgradable();
-    let parameters = world.resource::<ParticleParameters>().unwrap();
-    if parameters.reset {
-        drop(parameters);
-
+    let mut reader = world.resource_mut::<EventCursor<ResetParticles>>().unwrap();
+    let reset = !world.read_events(&mut reader).is_empty();
+    drop(reader);
+    if reset {
         let mut world = world.upgrade();
         reset_particles(&mut world);
     }
@@ -190,9 +194,7 @@ fn draw(world: &LockedWorld) {
     let world = world.lock_shared();
     let camera = world.resource::<Camera>().unwrap();
     let entities = world.components::<Particle>().unwrap();
-    let query = entities.query();
-    for id in query {
-        let particle = entities.get(&id).unwrap();
+    for (_, particle) in entities.iter() {
         draw_circle(
             (particle.position.x - camera.position.x) * camera.scale,
             (particle.position.y - camera.position.y) * camera.scale,
@@ -204,23 +206,28 @@ fn draw(world: &LockedWorld) {
 
 #[macroquad::main("")]
 async fn main() {
-    let world = LockedWorld::new();
+    let mut world = LockedWorld::new();
+    world
+        .lock_exclusive()
+        .insert_non_send_resource(UiState { hidden: false });
 
     let start_work = ThreadedWork::new()
         .add_system(start_particles)
         .add_system(start_camera);
-    let update_work = ThreadedWork::new()
-        .add_system(update_particles)
-        .add_system(check_reset);
+    let update_work = Work::new().add_system(update_particles);
     let draw_work = Work::new()
+        .add_system(check_reset)
         .add_system(draw)
         .add_system(update_ui)
         .add_system(update_camera);
 
     start_work.run(&world);
+    let mut runner = Runner::new()
+        .fixed_step(Duration::from_secs(1) / 30)
+        .add_fixed(update_work)
+        .add_variable(draw_work);
     loop {
-        update_work.run(&world);
-        draw_work.run(&world);
+        runner.tick(&mut world);
         next_frame().await;
     }
 }
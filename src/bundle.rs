@@ -0,0 +1,167 @@
+use std::any::Any;
+
+use crate::world::{EntityId, World};
+
+/// Components inserted and removed together. Implemented for tuples of
+/// components, structs get it through `impl_bundle!`.
+pub trait Bundle: Send + Sync + 'static + Sized {
+    fn insert_into(self, world: &mut World, entity: &EntityId);
+    /// Inserts `bundles[i]` into `entities[i]`.
+    fn insert_batch(world: &mut World, entities: &[EntityId], bundles: Vec<Self>) {
+        for (entity, bundle) in entities.iter().zip(bundles) {
+            bundle.insert_into(world, entity);
+        }
+    }
+    fn contained_by(world: &World, entity: &EntityId) -> bool;
+    /// Only removes the components if the entity has every one of them,
+    /// checked with all of their lists locked so a concurrent removal can't
+    /// leave the entity half stripped. The `on_remove` hooks run before
+    /// the lists are locked.
+    fn remove_from(world: &World, entity: &EntityId) -> Option<Self>;
+}
+
+/// Implements `Bundle` for a struct whose fields are components, up to
+/// eight of them.
+///
+/// ```
+/// struct Health(i32);
+/// struct Speed(f32);
+/// struct Runner {
+///     health: Health,
+///     speed: Speed,
+/// }
+/// retaker::impl_bundle!(Runner { health: Health, speed: Speed });
+/// ```
+#[macro_export]
+macro_rules! impl_bundle {
+    ($bundle:ident { $($field:ident: $component:ty),* $(,)? }) => {
+        impl $crate::bundle::Bundle for $bundle {
+            fn insert_into(
+                self,
+                world: &mut $crate::world::World,
+                entity: &$crate::world::EntityId,
+            ) {
+                $(world.insert(entity, self.$field);)*
+            }
+            fn insert_batch(
+                world: &mut $crate::world::World,
+                entities: &[$crate::world::EntityId],
+                bundles: ::std::vec::Vec<Self>,
+            ) {
+                $(let mut $field = ::std::vec::Vec::<$component>::with_capacity(bundles.len());)*
+                for bundle in bundles {
+                    $($field.push(bundle.$field);)*
+                }
+                $(world.insert_batch(entities, $field);)*
+            }
+            fn contained_by(world: &$crate::world::World, entity: &$crate::world::EntityId) -> bool {
+                $(world.components::<$component>().map_or(false, |list| list.contains(entity)))&&*
+            }
+            fn remove_from(
+                world: &$crate::world::World,
+                entity: &$crate::world::EntityId,
+            ) -> Option<Self> {
+                let ($($field,)*) =
+                    <($($component,)*) as $crate::bundle::Bundle>::remove_from(world, entity)?;
+                Some($bundle { $($field),* })
+            }
+        }
+    };
+}
+
+macro_rules! impl_bundle_for_tuple {
+    ($($name:ident $idx:tt),*) => {
+        impl<$($name: Any + Send + Sync),*> Bundle for ($($name,)*) {
+            fn insert_into(self, world: &mut World, entity: &EntityId) {
+                $(world.insert(entity, self.$idx);)*
+            }
+            fn insert_batch(world: &mut World, entities: &[EntityId], bundles: Vec<Self>) {
+                let mut columns = ($(Vec::<$name>::with_capacity(bundles.len()),)*);
+                for bundle in bundles {
+                    $(columns.$idx.push(bundle.$idx);)*
+                }
+                $(world.insert_batch(entities, columns.$idx);)*
+            }
+            fn contained_by(world: &World, entity: &EntityId) -> bool {
+                $(world.components::<$name>().map_or(false, |list| list.contains(entity)))&&*
+            }
+            fn remove_from(world: &World, entity: &EntityId) -> Option<Self> {
+                if !Self::contained_by(world, entity) {
+                    return None;
+                }
+                $(world.run_remove_hooks::<$name>(entity);)*
+                let mut lists = ($(world.components_mut::<$name>()?,)*);
+                if !($(lists.$idx.contains(entity))&&*) {
+                    return None;
+                }
+                Some(($(lists.$idx.remove(entity)?,)*))
+            }
+        }
+    };
+}
+
+impl_bundle_for_tuple!(A 0);
+impl_bundle_for_tuple!(A 0, B 1);
+impl_bundle_for_tuple!(A 0, B 1, C 2);
+impl_bundle_for_tuple!(A 0, B 1, C 2, D 3);
+impl_bundle_for_tuple!(A 0, B 1, C 2, D 3, E 4);
+impl_bundle_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5);
+impl_bundle_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+impl_bundle_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
+
+#[test]
+fn bundles_move_together() {
+    #[derive(Debug, PartialEq)]
+    struct Health(i32);
+    #[derive(Debug, PartialEq)]
+    struct Speed(u32);
+    #[derive(Debug, PartialEq)]
+    struct Armed;
+
+    #[derive(Debug, PartialEq)]
+    struct Soldier {
+        health: Health,
+        armed: Armed,
+    }
+    impl_bundle!(Soldier { health: Health, armed: Armed });
+
+    let mut world = World::new();
+    let runner = world.spawn((Health(3), Speed(2)));
+    assert_eq!(world.components::<Speed>().unwrap().get(&runner), Some(&Speed(2)));
+
+    assert_eq!(world.remove_bundle::<Soldier>(&runner), None);
+    assert!(
+        world.components::<Health>().unwrap().contains(&runner),
+        "incomplete bundle was partially removed"
+    );
+
+    world.insert_bundle(&runner, (Armed,));
+    assert_eq!(
+        world.remove_bundle::<Soldier>(&runner),
+        Some(Soldier {
+            health: Health(3),
+            armed: Armed,
+        })
+    );
+    assert_eq!(world.remove_bundle::<(Speed,)>(&runner), Some((Speed(2),)));
+    assert!(world.is_alive(&runner));
+}
+
+#[test]
+fn racing_removals_never_strip_bundles_halfway() {
+    struct Health;
+    struct Armed;
+
+    let mut world = World::new();
+    // stands for another thread disarming the soldier midway through
+    world.on_remove::<Health, _>(|world, soldier| {
+        world.remove::<Armed>(soldier);
+    });
+    let soldier = world.spawn((Health, Armed));
+
+    assert!(world.remove_bundle::<(Health, Armed)>(&soldier).is_none());
+    assert!(
+        world.components::<Health>().unwrap().contains(&soldier),
+        "a failed bundle removal took some of its components"
+    );
+}
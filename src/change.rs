@@ -0,0 +1,217 @@
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+
+use parking_lot::Mutex;
+
+use crate::world::EntityId;
+
+thread_local! {
+    /// The runs of the system running on this thread.
+    static CURRENT: RefCell<Option<Arc<Mutex<Vec<WorldRun>>>>> = const { RefCell::new(None) };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Tick(u64);
+
+/// The change tick of a `World`, moving forward every time a system first
+/// touches it in a run and on every write outside of a system. Components
+/// record the tick they were last written at.
+#[derive(Debug, Default)]
+pub(crate) struct WorldTicks {
+    clock: Arc<AtomicU64>,
+    /// How far the systems reading removals of this world got, removals
+    /// all of them saw are forgotten.
+    readers: Mutex<Vec<Weak<AtomicU64>>>,
+}
+
+/// Remembers when a system last ran on each world so its change filters
+/// only see what happened since.
+#[derive(Debug, Default)]
+pub(crate) struct SystemTicks {
+    runs: Arc<Mutex<Vec<WorldRun>>>,
+}
+
+#[derive(Debug)]
+struct WorldRun {
+    clock: Arc<AtomicU64>,
+    last_run: Tick,
+    /// Taken the first time the running system touches the world.
+    this_run: Option<Tick>,
+    /// The tick of the last removal the system saw, once it read any.
+    removals_seen: Option<Arc<AtomicU64>>,
+}
+
+struct RestoreCurrent(Option<Arc<Mutex<Vec<WorldRun>>>>);
+
+impl Drop for RestoreCurrent {
+    fn drop(&mut self) {
+        CURRENT.with(|current| *current.borrow_mut() = self.0.take());
+    }
+}
+
+impl SystemTicks {
+    pub(crate) fn run<R>(&self, system: impl FnOnce() -> R) -> R {
+        let _restore = RestoreCurrent(CURRENT.with(|current| {
+            current.borrow_mut().replace(self.runs.clone())
+        }));
+        let result = system();
+        for run in self.runs.lock().iter_mut() {
+            if let Some(this_run) = run.this_run.take() {
+                run.last_run = this_run;
+                if let Some(seen) = &run.removals_seen {
+                    seen.store(this_run.0, Ordering::Release);
+                }
+            }
+        }
+        result
+    }
+    /// Called instead of `run` when a run condition skips the system. Its
+    /// change filters still see everything since it last ran, but it
+    /// misses the removals made meanwhile so it doesn't hold them back.
+    pub(crate) fn skip(&self) {
+        for run in self.runs.lock().iter() {
+            if let Some(seen) = &run.removals_seen {
+                seen.store(run.clock.load(Ordering::Acquire), Ordering::Release);
+            }
+        }
+    }
+}
+
+impl WorldTicks {
+    /// Runs `f` on the current system's run of this world, if a system is
+    /// running on this thread.
+    fn with_run<R>(&self, f: impl FnOnce(&mut WorldRun) -> R) -> Option<R> {
+        let current = CURRENT.with(|current| current.borrow().clone())?;
+        let mut runs = current.lock();
+        let index = match runs
+            .iter()
+            .position(|run| Arc::ptr_eq(&run.clock, &self.clock))
+        {
+            Some(index) => index,
+            None => {
+                runs.push(WorldRun {
+                    clock: self.clock.clone(),
+                    last_run: Tick(0),
+                    this_run: None,
+                    removals_seen: None,
+                });
+                runs.len() - 1
+            }
+        };
+        let run = &mut runs[index];
+        if run.this_run.is_none() {
+            run.this_run = Some(self.next());
+        }
+        Some(f(run))
+    }
+    fn next(&self) -> Tick {
+        Tick(self.clock.fetch_add(1, Ordering::AcqRel) + 1)
+    }
+    /// The tick of the system running on this thread, so it doesn't see
+    /// its own writes on its next run. Outside of a system every call gets
+    /// a new tick.
+    pub(crate) fn now(&self) -> Tick {
+        self.with_run(|run| run.this_run.unwrap())
+            .unwrap_or_else(|| self.next())
+    }
+    /// When the system running on this thread last ran on this world, or
+    /// the beginning of time outside of a system.
+    pub(crate) fn last_run(&self) -> Tick {
+        self.with_run(|run| run.last_run).unwrap_or(Tick(0))
+    }
+    /// Like `last_run`, for reading removals. The running system holds
+    /// back the removals it didn't see yet from then on.
+    pub(crate) fn removals_seen(&self) -> Tick {
+        self.with_run(|run| match &run.removals_seen {
+            Some(seen) => Tick(seen.load(Ordering::Acquire)),
+            None => {
+                let seen = Arc::new(AtomicU64::new(run.last_run.0));
+                self.readers.lock().push(Arc::downgrade(&seen));
+                run.removals_seen = Some(seen);
+                run.last_run
+            }
+        })
+        .unwrap_or(Tick(0))
+    }
+    /// The removals every system reading them saw, `None` when no system
+    /// reads them so they're kept until `World::clear_trackers`.
+    pub(crate) fn removals_seen_by_all(&self) -> Option<Tick> {
+        let mut readers = self.readers.lock();
+        readers.retain(|seen| seen.strong_count() > 0);
+        readers
+            .iter()
+            .filter_map(Weak::upgrade)
+            .map(|seen| Tick(seen.load(Ordering::Acquire)))
+            .min()
+    }
+}
+
+/// Added and changed ticks of every component of a type, indexed by entity
+/// index. Only grows on insert, which needs the `World` mutably, so it can
+/// be read and marked without locking the component list.
+#[derive(Debug, Default)]
+pub struct TickTable {
+    added: Vec<AtomicU64>,
+    changed: Vec<AtomicU64>,
+    removed: Mutex<Vec<(EntityId, Tick)>>,
+}
+
+impl TickTable {
+    pub(crate) fn insert(&mut self, entity: &EntityId, is_new: bool, now: Tick) {
+        let index = entity.index() as usize;
+        self.reserve(index + 1);
+        if is_new {
+            *self.added[index].get_mut() = now.0;
+        }
+        *self.changed[index].get_mut() = now.0;
+    }
+    pub(crate) fn reserve(&mut self, end: usize) {
+        if end > self.added.len() {
+            self.added.resize_with(end, AtomicU64::default);
+            self.changed.resize_with(end, AtomicU64::default);
+        }
+    }
+    pub(crate) fn mark_changed(&self, entity: &EntityId, now: Tick) {
+        if let Some(changed) = self.changed.get(entity.index() as usize) {
+            changed.store(now.0, Ordering::Release);
+        }
+    }
+    /// `seen` is `WorldTicks::removals_seen_by_all`, removals up to it are
+    /// forgotten.
+    pub(crate) fn mark_removed(&self, entity: &EntityId, now: Tick, seen: Option<Tick>) {
+        let index = entity.index() as usize;
+        if let (Some(added), Some(changed)) = (self.added.get(index), self.changed.get(index)) {
+            added.store(0, Ordering::Release);
+            changed.store(0, Ordering::Release);
+        }
+        let mut removed = self.removed.lock();
+        if let Some(seen) = seen {
+            if removed.first().is_some_and(|(_, tick)| *tick <= seen) {
+                removed.retain(|(_, tick)| *tick > seen);
+            }
+        }
+        removed.push((entity.clone(), now));
+    }
+    pub(crate) fn is_added(&self, entity: &EntityId, since: Tick) -> bool {
+        self.added
+            .get(entity.index() as usize)
+            .is_some_and(|added| added.load(Ordering::Acquire) > since.0)
+    }
+    pub(crate) fn is_changed(&self, entity: &EntityId, since: Tick) -> bool {
+        self.changed
+            .get(entity.index() as usize)
+            .is_some_and(|changed| changed.load(Ordering::Acquire) > since.0)
+    }
+    pub(crate) fn removed_since(&self, since: Tick) -> Vec<EntityId> {
+        self.removed
+            .lock()
+            .iter()
+            .filter(|(_, tick)| *tick > since)
+            .map(|(entity, _)| entity.clone())
+            .collect()
+    }
+    pub(crate) fn clear_removed(&mut self) {
+        self.removed.get_mut().clear();
+    }
+}
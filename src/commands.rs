@@ -0,0 +1,118 @@
+use std::any::Any;
+
+use crate::bundle::Bundle;
+use crate::world::{EntityId, World};
+
+pub(crate) type Command = Box<dyn FnOnce(&mut World) + Send>;
+
+/// Structural changes queued from a shared `World`. They are moved into the
+/// world's queue when the `Commands` is dropped and run by
+/// `World::apply_commands`, usually between two `Work` runs.
+pub struct Commands<'w> {
+    world: &'w World,
+    queue: Vec<Command>,
+}
+
+impl<'w> Commands<'w> {
+    pub(crate) fn new(world: &'w World) -> Commands<'w> {
+        Commands {
+            world,
+            queue: vec![],
+        }
+    }
+    /// The id is reserved right away, its components are inserted when
+    /// the commands are applied.
+    pub fn spawn(&mut self) -> EntityId {
+        self.world.create_entity()
+    }
+    pub fn spawn_bundle<B: Bundle>(&mut self, bundle: B) -> EntityId {
+        let entity = self.spawn();
+        self.insert_bundle(&entity, bundle);
+        entity
+    }
+    pub fn despawn(&mut self, entity: &EntityId) {
+        let entity = entity.clone();
+        self.add(move |world| world.delete_entity(&entity));
+    }
+    pub fn despawn_recursive(&mut self, entity: &EntityId) {
+        let entity = entity.clone();
+        self.add(move |world| world.despawn_recursive(&entity));
+    }
+    pub fn set_parent(&mut self, child: &EntityId, parent: &EntityId) {
+        let (child, parent) = (child.clone(), parent.clone());
+        self.add(move |world| {
+            if world.is_alive(&child) && world.is_alive(&parent) {
+                world.set_parent(&child, &parent);
+            }
+        });
+    }
+    pub fn insert<T: Any + Send + Sync>(&mut self, entity: &EntityId, component: T) {
+        let entity = entity.clone();
+        self.add(move |world| {
+            if world.is_alive(&entity) {
+                world.insert(&entity, component);
+            }
+        });
+    }
+    pub fn insert_bundle<B: Bundle>(&mut self, entity: &EntityId, bundle: B) {
+        let entity = entity.clone();
+        self.add(move |world| {
+            if world.is_alive(&entity) {
+                world.insert_bundle(&entity, bundle);
+            }
+        });
+    }
+    pub fn remove<T: Any + Send + Sync>(&mut self, entity: &EntityId) {
+        let entity = entity.clone();
+        self.add(move |world| {
+            world.remove::<T>(&entity);
+        });
+    }
+    pub fn add<F: FnOnce(&mut World) + Send + 'static>(&mut self, command: F) {
+        self.queue.push(Box::new(command));
+    }
+}
+
+impl<'w> Drop for Commands<'w> {
+    fn drop(&mut self) {
+        if !self.queue.is_empty() {
+            self.world.queue_commands(&mut self.queue);
+        }
+    }
+}
+
+#[test]
+fn deferred_structural_changes() {
+    struct Health(i32);
+
+    let mut world = World::new();
+
+    let alice = world.create_entity();
+    world.insert(&alice, Health(0));
+
+    let bob = {
+        let mut commands = world.commands();
+        let bob = commands.spawn();
+        commands.insert(&bob, Health(3));
+        commands.despawn(&alice);
+        bob
+    };
+
+    assert!(world.is_alive(&alice), "commands were applied before the sync point");
+    assert!(world.components::<Health>().unwrap().get(&bob).is_none());
+
+    world.apply_commands();
+
+    assert!(!world.is_alive(&alice));
+    assert_eq!(world.components::<Health>().unwrap().get(&bob).map(|h| h.0), Some(3));
+
+    {
+        let mut commands = world.commands();
+        commands.despawn(&bob);
+        commands.insert(&bob, Health(5));
+    }
+    world.apply_commands();
+
+    assert!(!world.is_alive(&bob));
+    assert!(world.components::<Health>().unwrap().get(&bob).is_none());
+}
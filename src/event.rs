@@ -0,0 +1,228 @@
+use std::any::Any;
+use std::marker::PhantomData;
+
+use crate::world::{ResourceMut, ResourceRef, World};
+
+/// Double buffered queue of events of type `E`, stored as a resource.
+/// Events stay readable for two `World::update_events` calls, so every
+/// system gets to see them once no matter the order they run in. A
+/// `Runner` makes that call after every tick, code running systems on its
+/// own has to make it between runs.
+pub struct Events<E: Any + Send + Sync> {
+    previous: Vec<E>,
+    current: Vec<E>,
+    previous_start: usize,
+    current_start: usize,
+}
+
+/// Cursor of a single reader of `Events<E>`, remembers the events it
+/// already went through.
+pub struct EventCursor<E: Any + Send + Sync> {
+    last_read: usize,
+    marker: PhantomData<fn() -> E>,
+}
+
+/// The events a reader hadn't read yet, keeps `Events<E>` locked.
+pub struct ReadEvents<'a, E: Any + Send + Sync> {
+    events: ResourceRef<'a, Events<E>>,
+    start: usize,
+}
+
+/// System parameter reading the events of type `E` the system hadn't read
+/// yet, its cursor kept between runs. The system panics if the event
+/// wasn't added.
+pub struct EventReader<'w, E: Any + Send + Sync> {
+    pub(crate) events: ReadEvents<'w, E>,
+}
+
+/// System parameter sending events of type `E`.
+pub struct EventWriter<'w, E: Any + Send + Sync> {
+    pub(crate) events: ResourceMut<'w, Events<E>>,
+}
+
+impl<E: Any + Send + Sync> Default for Events<E> {
+    fn default() -> Self {
+        Events::new()
+    }
+}
+
+impl<E: Any + Send + Sync> Events<E> {
+    pub fn new() -> Events<E> {
+        Events {
+            previous: vec![],
+            current: vec![],
+            previous_start: 0,
+            current_start: 0,
+        }
+    }
+    pub fn send(&mut self, event: E) {
+        self.current.push(event);
+    }
+    /// Drops the events sent before the previous update.
+    pub fn update(&mut self) {
+        self.previous = std::mem::take(&mut self.current);
+        self.previous_start = self.current_start;
+        self.current_start += self.previous.len();
+    }
+    pub fn clear(&mut self) {
+        self.update();
+        self.update();
+    }
+    pub fn len(&self) -> usize {
+        self.previous.len() + self.current.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    fn event_count(&self) -> usize {
+        self.current_start + self.current.len()
+    }
+    /// A cursor that skips the events already sent.
+    pub fn cursor(&self) -> EventCursor<E> {
+        EventCursor {
+            last_read: self.event_count(),
+            marker: PhantomData,
+        }
+    }
+    fn iter_from(&self, start: usize) -> impl Iterator<Item = &E> {
+        let start = start.max(self.previous_start);
+        let previous = (start - self.previous_start).min(self.previous.len());
+        let current = start.saturating_sub(self.current_start);
+        self.previous[previous..]
+            .iter()
+            .chain(self.current[current.min(self.current.len())..].iter())
+    }
+}
+
+impl<E: Any + Send + Sync> Default for EventCursor<E> {
+    fn default() -> Self {
+        EventCursor::new()
+    }
+}
+
+impl<E: Any + Send + Sync> EventCursor<E> {
+    /// A cursor that reads every event still buffered.
+    pub fn new() -> EventCursor<E> {
+        EventCursor {
+            last_read: 0,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, E: Any + Send + Sync> ReadEvents<'a, E> {
+    pub(crate) fn new(events: ResourceRef<'a, Events<E>>, cursor: &mut EventCursor<E>) -> Self {
+        let start = cursor.last_read;
+        cursor.last_read = events.event_count();
+        ReadEvents { events, start }
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &E> {
+        self.events.iter_from(self.start)
+    }
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+}
+
+impl<'w, E: Any + Send + Sync> EventReader<'w, E> {
+    pub fn iter(&self) -> impl Iterator<Item = &E> {
+        self.events.iter()
+    }
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+}
+
+impl<'w, E: Any + Send + Sync> EventWriter<'w, E> {
+    pub fn send(&mut self, event: E) {
+        self.events.send(event);
+    }
+}
+
+pub(crate) fn update_events<E: Any + Send + Sync>(world: &World) {
+    if let Some(mut events) = world.resource_mut::<Events<E>>() {
+        events.update();
+    }
+}
+
+#[test]
+fn events_live_for_two_updates() {
+    #[derive(Debug, PartialEq)]
+    struct Damage(i32);
+
+    let mut world = World::new();
+    world.add_event::<Damage>();
+
+    let mut early = EventCursor::<Damage>::new();
+    let mut late = EventCursor::<Damage>::new();
+
+    world.send_event(Damage(1));
+    world.send_event(Damage(2));
+    assert_eq!(
+        world.read_events(&mut early).iter().collect::<Vec<_>>(),
+        [&Damage(1), &Damage(2)]
+    );
+    assert!(world.read_events(&mut early).is_empty(), "events were read twice");
+
+    world.update_events();
+    world.send_event(Damage(3));
+    assert_eq!(
+        world.read_events(&mut early).iter().collect::<Vec<_>>(),
+        [&Damage(3)]
+    );
+
+    world.update_events();
+    assert_eq!(
+        world.read_events(&mut late).iter().collect::<Vec<_>>(),
+        [&Damage(3)],
+        "events from two updates ago were not dropped"
+    );
+
+    world.send_event(Damage(4));
+    let mut skipping = world.resource::<Events<Damage>>().unwrap().cursor();
+    assert!(world.read_events(&mut skipping).is_empty());
+}
+
+#[test]
+fn systems_read_and_write_events() {
+    use crate::system::ResMut;
+    use crate::work::Work;
+
+    #[derive(Debug, PartialEq)]
+    struct Damage(i32);
+    #[derive(Default)]
+    struct Taken(Vec<i32>);
+
+    fn attack(mut damages: EventWriter<Damage>) {
+        damages.send(Damage(1));
+        damages.send(Damage(2));
+    }
+    fn take(damages: EventReader<Damage>, mut taken: ResMut<Taken>) {
+        taken.0.extend(damages.iter().map(|damage| damage.0));
+    }
+
+    let mut world = World::new();
+    world.add_event::<Damage>();
+    world.create_resource(Taken::default());
+
+    // `take` runs first, it reads the first run's events on the second one
+    let work = Work::new().add_system(take).add_system(attack);
+    work.run(&world);
+    assert!(world.resource::<Taken>().unwrap().0.is_empty());
+    world.update_events();
+    work.run(&world);
+    assert_eq!(world.resource::<Taken>().unwrap().0, [1, 2]);
+    world.update_events();
+    work.run(&world);
+    assert_eq!(
+        world.resource::<Taken>().unwrap().0,
+        [1, 2, 1, 2],
+        "events were read twice"
+    );
+}
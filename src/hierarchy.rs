@@ -0,0 +1,149 @@
+use crate::world::{EntityId, World};
+
+/// The entity this one is a child of. Only `World::set_parent` and
+/// `World::remove_parent` change it, so it always agrees with `Children`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Parent(EntityId);
+
+/// The children of an entity, in the order they were parented.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Children(Vec<EntityId>);
+
+impl Parent {
+    pub fn get(&self) -> &EntityId {
+        &self.0
+    }
+}
+
+impl Children {
+    pub fn iter(&self) -> impl Iterator<Item = &EntityId> {
+        self.0.iter()
+    }
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+    pub fn contains(&self, entity: &EntityId) -> bool {
+        self.0.contains(entity)
+    }
+}
+
+impl<'a> IntoIterator for &'a Children {
+    type IntoIter = std::slice::Iter<'a, EntityId>;
+    type Item = &'a EntityId;
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+pub(crate) fn set_parent(world: &mut World, child: &EntityId, parent: &EntityId) {
+    assert!(
+        world.is_alive(child) && world.is_alive(parent),
+        "tried to parent {:?} to {:?} but one of them is dead",
+        child,
+        parent
+    );
+    let mut ancestor = Some(parent.clone());
+    while let Some(entity) = ancestor {
+        assert!(
+            entity != *child,
+            "tried to parent {:?} to its own descendant {:?}",
+            child,
+            parent
+        );
+        ancestor = world.parent(&entity);
+    }
+    remove_parent(world, child);
+    world.insert(child, Parent(parent.clone()));
+    let pushed = world
+        .components_mut::<Children>()
+        .and_then(|mut list| {
+            list.get_mut(parent)?.0.push(child.clone());
+            Some(())
+        })
+        .is_some();
+    if !pushed {
+        world.insert(parent, Children(vec![child.clone()]));
+    }
+}
+
+pub(crate) fn remove_parent(world: &World, child: &EntityId) -> Option<EntityId> {
+    let Parent(parent) = world.remove::<Parent>(child)?;
+    let now_empty = world
+        .components_mut::<Children>()
+        .and_then(|mut list| {
+            let children = list.get_mut(&parent)?;
+            children.0.retain(|entity| entity != child);
+            Some(children.is_empty())
+        })
+        .unwrap_or(false);
+    if now_empty {
+        world.remove::<Children>(&parent);
+    }
+    Some(parent)
+}
+
+/// Keeps the hierarchy consistent when `entity` is about to be deleted: it
+/// leaves its parent and its children become roots.
+pub(crate) fn detach(world: &World, entity: &EntityId) {
+    remove_parent(world, entity);
+    if let Some(Children(children)) = world.remove::<Children>(entity) {
+        for child in children {
+            world.remove::<Parent>(&child);
+        }
+    }
+}
+
+pub(crate) fn descendants(world: &World, entity: &EntityId) -> Vec<EntityId> {
+    let mut descendants = vec![];
+    let mut stack = world.children(entity);
+    while let Some(entity) = stack.pop() {
+        stack.extend(world.children(&entity));
+        descendants.push(entity);
+    }
+    descendants
+}
+
+#[test]
+fn hierarchy_stays_consistent() {
+    let mut world = World::new();
+
+    let tank = world.create_entity();
+    let turret = world.create_entity();
+    let barrel = world.create_entity();
+    let other_tank = world.create_entity();
+
+    world.set_parent(&turret, &tank);
+    world.set_parent(&barrel, &turret);
+    assert_eq!(world.children(&tank), vec![turret.clone()]);
+    assert_eq!(world.parent(&barrel), Some(turret.clone()));
+    assert_eq!(world.descendants(&tank).len(), 2);
+
+    world.set_parent(&turret, &other_tank);
+    assert!(world.children(&tank).is_empty(), "old parent kept the child");
+    assert!(world.components::<Children>().unwrap().get(&tank).is_none());
+    assert_eq!(world.children(&other_tank), vec![turret.clone()]);
+
+    world.delete_entity(&turret);
+    assert!(world.children(&other_tank).is_empty(), "deleted child left behind");
+    assert_eq!(world.parent(&barrel), None, "child still points to a deleted parent");
+
+    world.set_parent(&barrel, &tank);
+    world.set_parent(&other_tank, &barrel);
+    world.despawn_recursive(&tank);
+    assert!(!world.is_alive(&tank));
+    assert!(!world.is_alive(&barrel));
+    assert!(!world.is_alive(&other_tank));
+}
+
+#[test]
+#[should_panic]
+fn hierarchy_cycle() {
+    let mut world = World::new();
+    let a = world.create_entity();
+    let b = world.create_entity();
+    world.set_parent(&b, &a);
+    world.set_parent(&a, &b);
+}
@@ -0,0 +1,64 @@
+use crate::world::{EntityId, World};
+
+/// Called with the world and the entity whose component changed. The world
+/// is shared, structural changes go through `World::commands`.
+pub type Hook = Box<dyn Fn(&World, &EntityId) + Send + Sync>;
+
+/// The hooks of a component type.
+#[derive(Default)]
+pub(crate) struct ComponentHooks {
+    /// When an entity that didn't have the component gets it.
+    pub(crate) on_add: Vec<Hook>,
+    /// Every time the component is inserted, after `on_add`.
+    pub(crate) on_insert: Vec<Hook>,
+    /// Right before the component is removed, it can still be read.
+    pub(crate) on_remove: Vec<Hook>,
+}
+
+pub(crate) fn run(hooks: &[Hook], world: &World, entity: &EntityId) {
+    for hook in hooks {
+        hook(world, entity);
+    }
+}
+
+#[test]
+fn hooks_follow_the_component() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    struct Voice;
+    struct Playing(AtomicUsize);
+
+    let mut world = World::new();
+    world.create_resource(Playing(AtomicUsize::new(0)));
+    world.on_add::<Voice, _>(|world, _| {
+        world.resource::<Playing>().unwrap().0.fetch_add(1, Ordering::Relaxed);
+    });
+    world.on_remove::<Voice, _>(|world, entity| {
+        let voices = world.components::<Voice>().unwrap();
+        assert!(voices.get(entity).is_some(), "component removed before its hook");
+        world.resource::<Playing>().unwrap().0.fetch_sub(1, Ordering::Relaxed);
+    });
+    let inserts = Arc::new(AtomicUsize::new(0));
+    let counter = inserts.clone();
+    world.on_insert::<Voice, _>(move |_, _| {
+        counter.fetch_add(1, Ordering::Relaxed);
+    });
+
+    let playing = |world: &World| world.resource::<Playing>().unwrap().0.load(Ordering::Relaxed);
+
+    let alice = world.create_entity();
+    let bob = world.create_entity();
+    world.insert(&alice, Voice);
+    world.insert(&alice, Voice);
+    world.insert(&bob, Voice);
+    assert_eq!(playing(&world), 2);
+    assert_eq!(inserts.load(Ordering::Relaxed), 3);
+
+    world.remove::<Voice>(&alice);
+    world.remove::<Voice>(&alice);
+    assert_eq!(playing(&world), 1);
+
+    world.delete_entity(&bob);
+    assert_eq!(playing(&world), 0, "delete_entity skipped the hook");
+}
@@ -1,2 +1,17 @@
+pub mod bundle;
+pub mod change;
+pub mod commands;
+pub mod event;
+pub mod hierarchy;
+pub mod hooks;
+mod lock_order;
+pub mod non_send;
+pub mod query;
+pub mod schedule;
+#[cfg(feature = "serde")]
+pub mod serialize;
+pub mod storage;
+pub mod system;
+pub mod time;
 pub mod work;
-pub mod world;
\ No newline at end of file
+pub mod world;
@@ -0,0 +1,379 @@
+use std::any::TypeId;
+
+use crate::world::ResourceId;
+
+#[cfg(debug_assertions)]
+use std::cell::{Cell, RefCell};
+#[cfg(debug_assertions)]
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[cfg(debug_assertions)]
+use hashbrown::{HashMap, HashSet};
+#[cfg(debug_assertions)]
+use parking_lot::Mutex;
+
+/// A component list or resource lock of a `World`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum LockId {
+    Component(TypeId),
+    Resource(ResourceId),
+}
+
+/// In debug builds, remembers which locks of a `World` were taken while
+/// holding which, and panics naming the types as soon as two of them are
+/// taken in both orders by code that can run at the same time, or a thread
+/// locks again what it holds in a conflicting way. Cycles of locks that
+/// are only read are ignored, and reads are recursive so a thread can read
+/// again what it reads. Release builds keep no bookkeeping.
+pub(crate) struct LockOrder {
+    #[cfg(debug_assertions)]
+    world: u64,
+    #[cfg(debug_assertions)]
+    graph: Mutex<LockGraph>,
+}
+
+#[cfg(debug_assertions)]
+#[derive(Default)]
+struct LockGraph {
+    names: HashMap<LockId, &'static str>,
+    /// `held -> taken` for every lock taken while holding another, along
+    /// with where it happened.
+    after: HashMap<LockId, HashMap<LockId, HashSet<Option<Origin>>>>,
+}
+
+/// The system of a runner taking locks. Systems of a runner only run at
+/// the same time as the other systems of their batch, so the orders they
+/// lock in can't deadlock with the ones of the other batches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct Origin {
+    #[cfg(debug_assertions)]
+    runner: u64,
+    #[cfg(debug_assertions)]
+    batch: (usize, usize),
+    #[cfg(debug_assertions)]
+    system: usize,
+}
+
+#[cfg(debug_assertions)]
+struct HeldLock {
+    world: u64,
+    id: LockId,
+    name: &'static str,
+    write: bool,
+}
+
+#[cfg(debug_assertions)]
+thread_local! {
+    static HELD: RefCell<Vec<HeldLock>> = const { RefCell::new(vec![]) };
+    static ORIGIN: Cell<Option<Origin>> = const { Cell::new(None) };
+}
+
+#[cfg(debug_assertions)]
+static NEXT_WORLD: AtomicU64 = AtomicU64::new(0);
+
+#[cfg(debug_assertions)]
+static NEXT_RUNNER: AtomicU64 = AtomicU64::new(0);
+
+/// Kept by a lock guard, forgets the lock when the guard is dropped.
+#[derive(Debug)]
+pub(crate) struct Held {
+    #[cfg(debug_assertions)]
+    world: u64,
+    #[cfg(debug_assertions)]
+    id: LockId,
+}
+
+#[cfg(debug_assertions)]
+fn mode(write: bool) -> &'static str {
+    if write {
+        "for writing"
+    } else {
+        "for reading"
+    }
+}
+
+/// An id for a runner, its systems being told apart by `Origin`.
+pub(crate) fn next_runner() -> u64 {
+    #[cfg(debug_assertions)]
+    return NEXT_RUNNER.fetch_add(1, Ordering::Relaxed);
+    #[cfg(not(debug_assertions))]
+    0
+}
+
+impl Origin {
+    /// System `system` of `runner`, running with the other systems of the
+    /// same `batch`.
+    #[cfg_attr(not(debug_assertions), allow(unused_variables))]
+    pub(crate) fn new(runner: u64, batch: (usize, usize), system: usize) -> Origin {
+        Origin {
+            #[cfg(debug_assertions)]
+            runner,
+            #[cfg(debug_assertions)]
+            batch,
+            #[cfg(debug_assertions)]
+            system,
+        }
+    }
+    /// Runs `f`, the locks it takes being taken by this system.
+    pub(crate) fn run<R>(self, f: impl FnOnce() -> R) -> R {
+        #[cfg(debug_assertions)]
+        {
+            struct Restore(Option<Origin>);
+            impl Drop for Restore {
+                fn drop(&mut self) {
+                    ORIGIN.with(|origin| origin.set(self.0));
+                }
+            }
+            let _restore = Restore(ORIGIN.with(|origin| origin.replace(Some(self))));
+            f()
+        }
+        #[cfg(not(debug_assertions))]
+        f()
+    }
+}
+
+/// Whether locks taken from `a` and `b` can be held at the same moment.
+/// Code outside of a runner can run alongside anything.
+#[cfg(debug_assertions)]
+fn can_overlap(a: &Option<Origin>, b: &Option<Origin>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) if a.runner == b.runner => a.batch == b.batch && a.system != b.system,
+        _ => true,
+    }
+}
+
+#[cfg(debug_assertions)]
+impl LockGraph {
+    /// The names of the locks on a path of `after` edges from `from` to `to`,
+    /// only following the edges taken by code that can run alongside
+    /// `origin`.
+    fn path(
+        &self,
+        from: &LockId,
+        to: &LockId,
+        origin: &Option<Origin>,
+    ) -> Option<Vec<&'static str>> {
+        let mut previous = HashMap::new();
+        let mut queue = vec![from.clone()];
+        while let Some(id) = queue.pop() {
+            if id == *to {
+                let mut path = vec![self.names[&id]];
+                let mut id = &id;
+                while let Some(before) = previous.get(id) {
+                    path.push(self.names[before]);
+                    id = before;
+                }
+                path.reverse();
+                return Some(path);
+            }
+            for (next, origins) in self.after.get(&id).into_iter().flatten() {
+                if !origins.iter().any(|other| can_overlap(origin, other)) {
+                    continue;
+                }
+                if *next != *from && !previous.contains_key(next) {
+                    previous.insert(next.clone(), id.clone());
+                    queue.push(next.clone());
+                }
+            }
+        }
+        None
+    }
+}
+
+impl LockOrder {
+    pub(crate) fn new() -> LockOrder {
+        LockOrder {
+            #[cfg(debug_assertions)]
+            world: NEXT_WORLD.fetch_add(1, Ordering::Relaxed),
+            #[cfg(debug_assertions)]
+            graph: Mutex::new(LockGraph::default()),
+        }
+    }
+    /// Called right before blocking on the lock `id`.
+    #[cfg_attr(not(debug_assertions), allow(unused_variables))]
+    pub(crate) fn acquire(&self, id: LockId, name: &'static str, write: bool) -> Held {
+        #[cfg(debug_assertions)]
+        HELD.with(|held| {
+            let held = held.borrow();
+            let mine = held.iter().filter(|lock| lock.world == self.world);
+            if let Some(lock) = mine.clone().find(|lock| lock.id == id) {
+                if lock.write || write {
+                    panic!(
+                        "{} is already locked {} by this thread, locking it {} again deadlocks",
+                        name,
+                        mode(lock.write),
+                        mode(write)
+                    );
+                }
+                // reads are recursive, reading again can't block on what
+                // this thread holds, so it orders nothing new
+                return;
+            }
+            let origin = ORIGIN.with(|origin| origin.get());
+            let mut graph = self.graph.lock();
+            graph.names.insert(id.clone(), name);
+            for lock in mine.filter(|lock| lock.write || write) {
+                if let Some(path) = graph.path(&id, &lock.id, &origin) {
+                    panic!(
+                        "locking {} while holding {} can deadlock, they were also locked in the order {}",
+                        name,
+                        lock.name,
+                        path.join(" -> ")
+                    );
+                }
+                graph
+                    .after
+                    .entry(lock.id.clone())
+                    .or_default()
+                    .entry(id.clone())
+                    .or_default()
+                    .insert(origin);
+            }
+        });
+        self.acquired(id, name, write)
+    }
+    /// Called once the lock `id` is taken without blocking, which can't
+    /// deadlock so isn't checked.
+    #[cfg_attr(not(debug_assertions), allow(unused_variables))]
+    pub(crate) fn acquired(&self, id: LockId, name: &'static str, write: bool) -> Held {
+        #[cfg(debug_assertions)]
+        {
+            HELD.with(|held| {
+                held.borrow_mut().push(HeldLock {
+                    world: self.world,
+                    id: id.clone(),
+                    name,
+                    write,
+                })
+            });
+            Held {
+                world: self.world,
+                id,
+            }
+        }
+        #[cfg(not(debug_assertions))]
+        Held {}
+    }
+}
+
+#[cfg(debug_assertions)]
+impl Drop for Held {
+    fn drop(&mut self) {
+        // the thread local may be gone already when a thread exits
+        let _ = HELD.try_with(|held| {
+            let mut held = held.borrow_mut();
+            if let Some(index) = held
+                .iter()
+                .rposition(|lock| lock.world == self.world && lock.id == self.id)
+            {
+                held.remove(index);
+            }
+        });
+    }
+}
+
+#[cfg(debug_assertions)]
+#[test]
+#[should_panic(expected = "is already locked for writing by this thread")]
+fn double_borrow_panics() {
+    use crate::world::World;
+
+    struct Health;
+
+    let mut world = World::new();
+    let entity = world.create_entity();
+    world.insert(&entity, Health);
+
+    let _healths = world.components_mut::<Health>().unwrap();
+    world.components::<Health>();
+}
+
+#[cfg(debug_assertions)]
+#[test]
+#[should_panic(expected = "is already locked for reading by this thread")]
+fn write_after_read_panics() {
+    use crate::world::World;
+
+    struct Health;
+
+    let mut world = World::new();
+    let entity = world.create_entity();
+    world.insert(&entity, Health);
+
+    let _healths = world.components::<Health>().unwrap();
+    let _reentrant = world.components::<Health>().unwrap();
+    world.components_mut::<Health>();
+}
+
+#[cfg(debug_assertions)]
+#[test]
+fn lock_order_cycles_panic() {
+    use std::any::type_name;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    use crate::world::World;
+
+    struct Health;
+    struct Faction;
+    struct Turn;
+
+    let mut world = World::new();
+    let entity = world.create_entity();
+    world.insert(&entity, Health);
+    world.insert(&entity, Faction);
+    world.create_resource(Turn);
+
+    {
+        let _healths = world.components_mut::<Health>().unwrap();
+        let _factions = world.components_mut::<Faction>().unwrap();
+    }
+    {
+        let _factions = world.components_mut::<Faction>().unwrap();
+        let _turn = world.resource::<Turn>().unwrap();
+    }
+    {
+        // only reading both orders nothing
+        let _turn = world.resource::<Turn>().unwrap();
+        let _factions = world.components::<Faction>().unwrap();
+    }
+
+    let message = catch_unwind(AssertUnwindSafe(|| {
+        let _turn = world.resource_mut::<Turn>().unwrap();
+        let _healths = world.components::<Health>().unwrap();
+    }))
+    .unwrap_err();
+    let message = message.downcast_ref::<String>().unwrap();
+    assert!(
+        message.contains(&format!(
+            "{} -> {} -> {}",
+            type_name::<Health>(),
+            type_name::<Faction>(),
+            type_name::<Turn>()
+        )),
+        "{}",
+        message
+    );
+}
+
+#[cfg(debug_assertions)]
+#[test]
+fn systems_running_apart_order_nothing() {
+    use crate::query::Query;
+    use crate::work::Work;
+    use crate::world::World;
+
+    struct Health;
+    struct Faction;
+
+    fn a(_: Query<&mut Health>, _: Query<&Faction>) {}
+    fn b(_: Query<&mut Faction>, _: Query<&Health>) {}
+
+    let mut world = World::new();
+    let entity = world.create_entity();
+    world.insert(&entity, Health);
+    world.insert(&entity, Faction);
+
+    let work = Work::new().add_system(a).add_system(b);
+    work.run(&world);
+    work.run(&world);
+}
@@ -0,0 +1,159 @@
+use std::any::{type_name, Any, TypeId};
+use std::cell::{Ref, RefCell, RefMut};
+use std::ops::{Deref, DerefMut};
+use std::thread::{self, ThreadId};
+
+use hashbrown::HashMap;
+
+/// Resources that aren't `Send` or `Sync`, like graphics contexts or `Rc`
+/// handles. Only the thread that created the `World` can reach them.
+pub(crate) struct NonSendResources {
+    owner: ThreadId,
+    table: HashMap<TypeId, Box<dyn Any>>,
+}
+
+/// Shared access to a non-send resource.
+pub struct NonSendResourceRef<'a, T: Any> {
+    borrow: Ref<'a, T>,
+}
+
+/// Exclusive access to a non-send resource.
+pub struct NonSendResourceMut<'a, T: Any> {
+    borrow: RefMut<'a, T>,
+}
+
+// SAFETY: the resources are only reached through `assert_owner`, so they
+// never leave the thread that created them, and are leaked rather than
+// dropped anywhere else.
+unsafe impl Send for NonSendResources {}
+unsafe impl Sync for NonSendResources {}
+
+impl NonSendResources {
+    pub(crate) fn new() -> NonSendResources {
+        NonSendResources {
+            owner: thread::current().id(),
+            table: HashMap::new(),
+        }
+    }
+    pub(crate) fn is_owner(&self) -> bool {
+        thread::current().id() == self.owner
+    }
+    fn assert_owner<T>(&self) {
+        assert!(
+            self.is_owner(),
+            "non-send resource {} accessed from {:?}, only the thread that created the world ({:?}) can",
+            type_name::<T>(),
+            thread::current().id(),
+            self.owner
+        );
+    }
+    fn cell<T: Any>(&self) -> Option<&RefCell<T>> {
+        self.assert_owner::<T>();
+        self.table
+            .get(&TypeId::of::<T>())
+            .map(|cell| cell.downcast_ref::<RefCell<T>>().unwrap())
+    }
+    pub(crate) fn insert<T: Any>(&mut self, resource: T) -> Option<T> {
+        self.assert_owner::<T>();
+        self.table
+            .insert(TypeId::of::<T>(), Box::new(RefCell::new(resource)))
+            .map(|previous| previous.downcast::<RefCell<T>>().unwrap().into_inner())
+    }
+    pub(crate) fn remove<T: Any>(&mut self) -> Option<T> {
+        self.assert_owner::<T>();
+        self.table
+            .remove(&TypeId::of::<T>())
+            .map(|resource| resource.downcast::<RefCell<T>>().unwrap().into_inner())
+    }
+    pub(crate) fn contains<T: Any>(&self) -> bool {
+        self.cell::<T>().is_some()
+    }
+    pub(crate) fn get<T: Any>(&self) -> Option<NonSendResourceRef<'_, T>> {
+        let borrow = self.cell::<T>()?.try_borrow().unwrap_or_else(|_| {
+            panic!(
+                "non-send resource {} is already borrowed mutably",
+                type_name::<T>()
+            )
+        });
+        Some(NonSendResourceRef { borrow })
+    }
+    pub(crate) fn get_mut<T: Any>(&self) -> Option<NonSendResourceMut<'_, T>> {
+        let borrow = self.cell::<T>()?.try_borrow_mut().unwrap_or_else(|_| {
+            panic!("non-send resource {} is already borrowed", type_name::<T>())
+        });
+        Some(NonSendResourceMut { borrow })
+    }
+}
+
+impl Drop for NonSendResources {
+    fn drop(&mut self) {
+        // dropping them on another thread would be unsound, leak them instead
+        if !self.is_owner() && !self.table.is_empty() {
+            std::mem::forget(std::mem::take(&mut self.table));
+        }
+    }
+}
+
+impl<'a, T: Any> Deref for NonSendResourceRef<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        &self.borrow
+    }
+}
+
+impl<'a, T: Any> Deref for NonSendResourceMut<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        &self.borrow
+    }
+}
+
+impl<'a, T: Any> DerefMut for NonSendResourceMut<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.borrow
+    }
+}
+
+#[test]
+fn non_send_systems_stay_on_the_main_thread() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use crate::system::{NonSendMut, Res};
+    use crate::work::{Access, Scheduler};
+    use crate::world::World;
+
+    struct Window(Rc<Cell<u32>>);
+    struct Frame(u32);
+
+    fn present(window: NonSendMut<Window>, frame: Res<Frame>) {
+        window.0.set(window.0.get() + frame.0);
+    }
+    fn simulate(_: Res<Frame>) {}
+
+    let frames = Rc::new(Cell::new(0));
+    let mut world = World::new();
+    world.create_resource(Frame(1));
+    world.insert_non_send_resource(Window(frames.clone()));
+
+    // the declared accesses leave out the non-send resource
+    let scheduler = Scheduler::new()
+        .add_system(present, Access::new().read_resource::<Frame>())
+        .add_system(simulate, Access::new().read_resource::<Frame>());
+    for _ in 0..8 {
+        scheduler.run(&world);
+    }
+    assert_eq!(frames.get(), 8);
+
+    let touched_elsewhere = std::thread::scope(|scope| {
+        scope
+            .spawn(|| world.contains_non_send_resource::<Window>())
+            .join()
+            .is_err()
+    });
+    assert!(
+        touched_elsewhere,
+        "non-send resource reached from another thread"
+    );
+    assert!(world.take_non_send_resource::<Window>().is_some());
+}
@@ -0,0 +1,644 @@
+use std::any::{type_name, Any, TypeId};
+use std::marker::PhantomData;
+use std::ptr::addr_of_mut;
+use std::slice::Iter;
+
+use crate::change::{Tick, TickTable};
+use crate::world::{ComponentListMut, ComponentListRef, EntityId, World};
+
+/// A single element of a query: `&T`, `&mut T`, `Option<&T>` or
+/// `Option<&mut T>`. Locks the component list of `T` for as long as the
+/// query lives.
+pub trait QueryTerm<'w> {
+    type State;
+    fn lock(world: &'w World) -> Self::State;
+    /// The component lists the term locks, and whether it writes to them.
+    fn type_access(access: &mut Vec<(TypeId, &'static str, bool)>);
+    /// Number of entities the term can match, `None` if it matches any.
+    fn size(state: &Self::State) -> Option<usize>;
+    fn entities(state: &Self::State) -> Vec<EntityId>;
+    fn matches(state: &Self::State, entity: &EntityId) -> bool;
+    /// Whether `entity` has a `type_id` component, `None` if the term
+    /// doesn't lock that list.
+    fn has(state: &Self::State, type_id: TypeId, entity: &EntityId) -> Option<bool>;
+}
+
+pub trait FetchTerm<'w, 'q>: QueryTerm<'w> {
+    type Item;
+    /// # Safety
+    /// `entity` must match the term and no other item fetched for the same
+    /// entity may still be alive if the term is mutable.
+    unsafe fn fetch(state: *mut Self::State, entity: &EntityId) -> Self::Item;
+}
+
+/// What a query yields: a term or a tuple of terms, always preceded by the
+/// id of the entity they belong to.
+pub trait QueryData<'w> {
+    type State;
+    fn lock(world: &'w World) -> Self::State;
+    fn type_access(access: &mut Vec<(TypeId, &'static str, bool)>);
+    fn entities(state: &Self::State) -> Option<Vec<EntityId>>;
+    fn matches(state: &Self::State, entity: &EntityId) -> bool;
+    /// Same as `QueryTerm::has`, for any of the terms.
+    fn has(state: &Self::State, type_id: TypeId, entity: &EntityId) -> Option<bool>;
+}
+
+pub trait FetchData<'w, 'q>: QueryData<'w> {
+    type Item;
+    /// # Safety
+    /// Same contract as `FetchTerm::fetch`.
+    unsafe fn fetch(state: *mut Self::State, entity: &EntityId) -> Self::Item;
+}
+
+/// Implemented by queries that only read, so they can be iterated from a
+/// shared reference.
+///
+/// # Safety
+/// Fetching must never hand out a mutable reference.
+pub unsafe trait ReadOnlyQuery {}
+
+/// Narrows the entities of a query. A filter on a component list the
+/// query data already locks reads it through the data instead of locking
+/// it again.
+pub trait QueryFilter<'w> {
+    type State;
+    /// `data` are the component lists locked by the query data.
+    fn lock(world: &'w World, data: &[TypeId]) -> Self::State;
+    fn type_access(access: &mut Vec<(TypeId, &'static str, bool)>);
+    fn matches<Q: QueryData<'w>>(state: &Self::State, data: &Q::State, entity: &EntityId) -> bool;
+}
+
+pub struct With<T>(PhantomData<T>);
+
+pub struct Without<T>(PhantomData<T>);
+
+/// Matches entities whose `T` was inserted since the calling system last
+/// ran.
+pub struct Added<T>(PhantomData<T>);
+
+/// Matches entities whose `T` was inserted or mutably accessed since the
+/// calling system last ran.
+pub struct Changed<T>(PhantomData<T>);
+
+pub struct Query<'w, Q: QueryData<'w>, F: QueryFilter<'w> = ()> {
+    state: Q::State,
+    filter: F::State,
+    entities: Vec<EntityId>,
+}
+
+pub struct QueryIter<'w, 'q, Q: QueryData<'w>> {
+    state: *mut Q::State,
+    entities: Iter<'q, EntityId>,
+    marker: PhantomData<&'q mut Q::State>,
+}
+
+impl<'w, T: Any + Send + Sync> QueryTerm<'w> for &T {
+    type State = Option<ComponentListRef<'w, T>>;
+    fn lock(world: &'w World) -> Self::State {
+        world.components::<T>()
+    }
+    fn type_access(access: &mut Vec<(TypeId, &'static str, bool)>) {
+        access.push((TypeId::of::<T>(), type_name::<T>(), false));
+    }
+    fn size(state: &Self::State) -> Option<usize> {
+        Some(state.as_ref().map_or(0, |list| list.lock.len()))
+    }
+    fn entities(state: &Self::State) -> Vec<EntityId> {
+        state
+            .as_ref()
+            .map_or(vec![], |list| list.lock.entities())
+    }
+    fn matches(state: &Self::State, entity: &EntityId) -> bool {
+        state
+            .as_ref()
+            .is_some_and(|list| list.lock.contains(entity))
+    }
+    fn has(state: &Self::State, type_id: TypeId, entity: &EntityId) -> Option<bool> {
+        if type_id != TypeId::of::<T>() {
+            return None;
+        }
+        Some(
+            state
+                .as_ref()
+                .is_some_and(|list| list.lock.contains(entity)),
+        )
+    }
+}
+
+impl<'w, 'q, 'a, T: Any + Send + Sync> FetchTerm<'w, 'q> for &'a T {
+    type Item = &'q T;
+    unsafe fn fetch(state: *mut Self::State, entity: &EntityId) -> Self::Item {
+        let list = (*state).as_ref().unwrap();
+        &*(list.lock.get(entity).unwrap() as *const T)
+    }
+}
+
+impl<'w, T: Any + Send + Sync> QueryTerm<'w> for &mut T {
+    type State = Option<ComponentListMut<'w, T>>;
+    fn lock(world: &'w World) -> Self::State {
+        world.components_mut::<T>()
+    }
+    fn type_access(access: &mut Vec<(TypeId, &'static str, bool)>) {
+        access.push((TypeId::of::<T>(), type_name::<T>(), true));
+    }
+    fn size(state: &Self::State) -> Option<usize> {
+        Some(state.as_ref().map_or(0, |list| list.lock.len()))
+    }
+    fn entities(state: &Self::State) -> Vec<EntityId> {
+        state
+            .as_ref()
+            .map_or(vec![], |list| list.lock.entities())
+    }
+    fn matches(state: &Self::State, entity: &EntityId) -> bool {
+        state
+            .as_ref()
+            .is_some_and(|list| list.lock.contains(entity))
+    }
+    fn has(state: &Self::State, type_id: TypeId, entity: &EntityId) -> Option<bool> {
+        if type_id != TypeId::of::<T>() {
+            return None;
+        }
+        Some(
+            state
+                .as_ref()
+                .is_some_and(|list| list.lock.contains(entity)),
+        )
+    }
+}
+
+impl<'w, 'q, 'a, T: Any + Send + Sync> FetchTerm<'w, 'q> for &'a mut T {
+    type Item = &'q mut T;
+    unsafe fn fetch(state: *mut Self::State, entity: &EntityId) -> Self::Item {
+        let list = (*state).as_mut().unwrap();
+        &mut *list.get_mut_ptr(entity).unwrap()
+    }
+}
+
+impl<'w, T: Any + Send + Sync> QueryTerm<'w> for Option<&T> {
+    type State = Option<ComponentListRef<'w, T>>;
+    fn lock(world: &'w World) -> Self::State {
+        world.components::<T>()
+    }
+    fn type_access(access: &mut Vec<(TypeId, &'static str, bool)>) {
+        access.push((TypeId::of::<T>(), type_name::<T>(), false));
+    }
+    fn size(_: &Self::State) -> Option<usize> {
+        None
+    }
+    fn entities(_: &Self::State) -> Vec<EntityId> {
+        vec![]
+    }
+    fn matches(_: &Self::State, _: &EntityId) -> bool {
+        true
+    }
+    fn has(state: &Self::State, type_id: TypeId, entity: &EntityId) -> Option<bool> {
+        if type_id != TypeId::of::<T>() {
+            return None;
+        }
+        Some(
+            state
+                .as_ref()
+                .is_some_and(|list| list.lock.contains(entity)),
+        )
+    }
+}
+
+impl<'w, 'q, 'a, T: Any + Send + Sync> FetchTerm<'w, 'q> for Option<&'a T> {
+    type Item = Option<&'q T>;
+    unsafe fn fetch(state: *mut Self::State, entity: &EntityId) -> Self::Item {
+        let list = (*state).as_ref()?;
+        list.lock.get(entity).map(|component| &*(component as *const T))
+    }
+}
+
+impl<'w, T: Any + Send + Sync> QueryTerm<'w> for Option<&mut T> {
+    type State = Option<ComponentListMut<'w, T>>;
+    fn lock(world: &'w World) -> Self::State {
+        world.components_mut::<T>()
+    }
+    fn type_access(access: &mut Vec<(TypeId, &'static str, bool)>) {
+        access.push((TypeId::of::<T>(), type_name::<T>(), true));
+    }
+    fn size(_: &Self::State) -> Option<usize> {
+        None
+    }
+    fn entities(_: &Self::State) -> Vec<EntityId> {
+        vec![]
+    }
+    fn matches(_: &Self::State, _: &EntityId) -> bool {
+        true
+    }
+    fn has(state: &Self::State, type_id: TypeId, entity: &EntityId) -> Option<bool> {
+        if type_id != TypeId::of::<T>() {
+            return None;
+        }
+        Some(
+            state
+                .as_ref()
+                .is_some_and(|list| list.lock.contains(entity)),
+        )
+    }
+}
+
+impl<'w, 'q, 'a, T: Any + Send + Sync> FetchTerm<'w, 'q> for Option<&'a mut T> {
+    type Item = Option<&'q mut T>;
+    unsafe fn fetch(state: *mut Self::State, entity: &EntityId) -> Self::Item {
+        let list = (*state).as_mut()?;
+        list.get_mut_ptr(entity).map(|component| &mut *component)
+    }
+}
+
+macro_rules! impl_query_data_for_term {
+    ($($term:ty),*) => {
+        $(
+            impl<'w, 'a, T: Any + Send + Sync> QueryData<'w> for $term {
+                type State = <$term as QueryTerm<'w>>::State;
+                fn lock(world: &'w World) -> Self::State {
+                    <$term as QueryTerm<'w>>::lock(world)
+                }
+                fn type_access(access: &mut Vec<(TypeId, &'static str, bool)>) {
+                    <$term as QueryTerm<'w>>::type_access(access)
+                }
+                fn entities(state: &Self::State) -> Option<Vec<EntityId>> {
+                    <$term as QueryTerm<'w>>::size(state)?;
+                    Some(<$term as QueryTerm<'w>>::entities(state))
+                }
+                fn matches(state: &Self::State, entity: &EntityId) -> bool {
+                    <$term as QueryTerm<'w>>::matches(state, entity)
+                }
+                fn has(state: &Self::State, type_id: TypeId, entity: &EntityId) -> Option<bool> {
+                    <$term as QueryTerm<'w>>::has(state, type_id, entity)
+                }
+            }
+
+            impl<'w, 'q, 'a, T: Any + Send + Sync> FetchData<'w, 'q> for $term {
+                type Item = (EntityId, <$term as FetchTerm<'w, 'q>>::Item);
+                unsafe fn fetch(state: *mut Self::State, entity: &EntityId) -> Self::Item {
+                    (entity.clone(), <$term as FetchTerm<'w, 'q>>::fetch(state, entity))
+                }
+            }
+        )*
+    };
+}
+
+impl_query_data_for_term!(&'a T, &'a mut T, Option<&'a T>, Option<&'a mut T>);
+
+unsafe impl<T> ReadOnlyQuery for &T {}
+unsafe impl<T> ReadOnlyQuery for Option<&T> {}
+
+macro_rules! impl_query_data_for_tuple {
+    ($($name:ident $idx:tt),*) => {
+        impl<'w, $($name: QueryTerm<'w>),*> QueryData<'w> for ($($name,)*) {
+            type State = ($($name::State,)*);
+            fn lock(world: &'w World) -> Self::State {
+                ($($name::lock(world),)*)
+            }
+            fn type_access(access: &mut Vec<(TypeId, &'static str, bool)>) {
+                $($name::type_access(access);)*
+            }
+            fn entities(state: &Self::State) -> Option<Vec<EntityId>> {
+                let mut smallest: Option<(usize, usize)> = None;
+                $(
+                    if let Some(size) = $name::size(&state.$idx) {
+                        if smallest.map_or(true, |(smallest, _)| size < smallest) {
+                            smallest = Some((size, $idx));
+                        }
+                    }
+                )*
+                match smallest {
+                    $(Some((_, $idx)) => Some($name::entities(&state.$idx)),)*
+                    _ => None,
+                }
+            }
+            fn matches(state: &Self::State, entity: &EntityId) -> bool {
+                $($name::matches(&state.$idx, entity))&&*
+            }
+            fn has(state: &Self::State, type_id: TypeId, entity: &EntityId) -> Option<bool> {
+                None$(.or_else(|| $name::has(&state.$idx, type_id, entity)))*
+            }
+        }
+
+        impl<'w, 'q, $($name: FetchTerm<'w, 'q>),*> FetchData<'w, 'q> for ($($name,)*) {
+            type Item = (EntityId, $($name::Item,)*);
+            unsafe fn fetch(state: *mut Self::State, entity: &EntityId) -> Self::Item {
+                (entity.clone(), $($name::fetch(addr_of_mut!((*state).$idx), entity),)*)
+            }
+        }
+
+        unsafe impl<$($name: ReadOnlyQuery),*> ReadOnlyQuery for ($($name,)*) {}
+    };
+}
+
+impl_query_data_for_tuple!(A 0);
+impl_query_data_for_tuple!(A 0, B 1);
+impl_query_data_for_tuple!(A 0, B 1, C 2);
+impl_query_data_for_tuple!(A 0, B 1, C 2, D 3);
+impl_query_data_for_tuple!(A 0, B 1, C 2, D 3, E 4);
+impl_query_data_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5);
+impl_query_data_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+impl_query_data_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
+
+impl<'w> QueryFilter<'w> for () {
+    type State = ();
+    fn lock(_: &'w World, _: &[TypeId]) -> Self::State {}
+    fn type_access(_: &mut Vec<(TypeId, &'static str, bool)>) {}
+    fn matches<Q: QueryData<'w>>(_: &Self::State, _: &Q::State, _: &EntityId) -> bool {
+        true
+    }
+}
+
+impl<'w, T: Any + Send + Sync> QueryFilter<'w> for With<T> {
+    /// `None` when the query data locks `T`.
+    type State = Option<Option<ComponentListRef<'w, T>>>;
+    fn lock(world: &'w World, data: &[TypeId]) -> Self::State {
+        if data.contains(&TypeId::of::<T>()) {
+            return None;
+        }
+        Some(world.components::<T>())
+    }
+    fn type_access(access: &mut Vec<(TypeId, &'static str, bool)>) {
+        access.push((TypeId::of::<T>(), type_name::<T>(), false));
+    }
+    fn matches<Q: QueryData<'w>>(state: &Self::State, data: &Q::State, entity: &EntityId) -> bool {
+        let has = match state {
+            Some(list) => list
+                .as_ref()
+                .is_some_and(|list| list.lock.contains(entity)),
+            None => Q::has(data, TypeId::of::<T>(), entity).unwrap_or(false),
+        };
+        has
+    }
+}
+
+impl<'w, T: Any + Send + Sync> QueryFilter<'w> for Without<T> {
+    /// `None` when the query data locks `T`.
+    type State = Option<Option<ComponentListRef<'w, T>>>;
+    fn lock(world: &'w World, data: &[TypeId]) -> Self::State {
+        if data.contains(&TypeId::of::<T>()) {
+            return None;
+        }
+        Some(world.components::<T>())
+    }
+    fn type_access(access: &mut Vec<(TypeId, &'static str, bool)>) {
+        access.push((TypeId::of::<T>(), type_name::<T>(), false));
+    }
+    fn matches<Q: QueryData<'w>>(state: &Self::State, data: &Q::State, entity: &EntityId) -> bool {
+        let has = match state {
+            Some(list) => list
+                .as_ref()
+                .is_some_and(|list| list.lock.contains(entity)),
+            None => Q::has(data, TypeId::of::<T>(), entity).unwrap_or(false),
+        };
+        !has
+    }
+}
+
+impl<'w, T: Any + Send + Sync> QueryFilter<'w> for Added<T> {
+    type State = (Option<&'w TickTable>, Tick);
+    fn lock(world: &'w World, _: &[TypeId]) -> Self::State {
+        (world.component_ticks::<T>(), world.last_run())
+    }
+    fn type_access(_: &mut Vec<(TypeId, &'static str, bool)>) {}
+    fn matches<Q: QueryData<'w>>(
+        (ticks, last_run): &Self::State,
+        _: &Q::State,
+        entity: &EntityId,
+    ) -> bool {
+        ticks.is_some_and(|ticks| ticks.is_added(entity, *last_run))
+    }
+}
+
+impl<'w, T: Any + Send + Sync> QueryFilter<'w> for Changed<T> {
+    type State = (Option<&'w TickTable>, Tick);
+    fn lock(world: &'w World, _: &[TypeId]) -> Self::State {
+        (world.component_ticks::<T>(), world.last_run())
+    }
+    fn type_access(_: &mut Vec<(TypeId, &'static str, bool)>) {}
+    fn matches<Q: QueryData<'w>>(
+        (ticks, last_run): &Self::State,
+        _: &Q::State,
+        entity: &EntityId,
+    ) -> bool {
+        ticks.is_some_and(|ticks| ticks.is_changed(entity, *last_run))
+    }
+}
+
+macro_rules! impl_query_filter_for_tuple {
+    ($($name:ident $idx:tt),*) => {
+        impl<'w, $($name: QueryFilter<'w>),*> QueryFilter<'w> for ($($name,)*) {
+            type State = ($($name::State,)*);
+            fn lock(world: &'w World, data: &[TypeId]) -> Self::State {
+                ($($name::lock(world, data),)*)
+            }
+            fn type_access(access: &mut Vec<(TypeId, &'static str, bool)>) {
+                $($name::type_access(access);)*
+            }
+            fn matches<Q: QueryData<'w>>(
+                state: &Self::State,
+                data: &Q::State,
+                entity: &EntityId,
+            ) -> bool {
+                $($name::matches::<Q>(&state.$idx, data, entity))&&*
+            }
+        }
+    };
+}
+
+impl_query_filter_for_tuple!(A 0);
+impl_query_filter_for_tuple!(A 0, B 1);
+impl_query_filter_for_tuple!(A 0, B 1, C 2);
+impl_query_filter_for_tuple!(A 0, B 1, C 2, D 3);
+
+impl<'w, Q: QueryData<'w>, F: QueryFilter<'w>> Query<'w, Q, F> {
+    pub(crate) fn new(world: &'w World) -> Query<'w, Q, F> {
+        let mut access = vec![];
+        Q::type_access(&mut access);
+        for (i, (type_id, name, write)) in access.iter().enumerate() {
+            if access[..i]
+                .iter()
+                .any(|(other, _, other_write)| other == type_id && (*write || *other_write))
+            {
+                panic!("query locks the component list of {} in conflicting ways", name);
+            }
+        }
+        let data = access
+            .iter()
+            .map(|(type_id, _, _)| *type_id)
+            .collect::<Vec<_>>();
+
+        let state = Q::lock(world);
+        let filter = F::lock(world, &data);
+        let mut entities = Q::entities(&state).unwrap_or_else(|| world.alive_entities());
+        entities.retain(|entity| {
+            Q::matches(&state, entity) && F::matches::<Q>(&filter, &state, entity)
+        });
+
+        Query {
+            state,
+            filter,
+            entities,
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+    pub fn entities(&self) -> &[EntityId] {
+        &self.entities
+    }
+    pub fn contains(&self, entity: &EntityId) -> bool {
+        Q::matches(&self.state, entity) && F::matches::<Q>(&self.filter, &self.state, entity)
+    }
+    pub fn iter<'q>(&'q self) -> QueryIter<'w, 'q, Q>
+    where
+        Q: FetchData<'w, 'q> + ReadOnlyQuery,
+    {
+        QueryIter {
+            state: &self.state as *const Q::State as *mut Q::State,
+            entities: self.entities.iter(),
+            marker: PhantomData,
+        }
+    }
+    pub fn iter_mut<'q>(&'q mut self) -> QueryIter<'w, 'q, Q>
+    where
+        Q: FetchData<'w, 'q>,
+    {
+        QueryIter {
+            state: &mut self.state,
+            entities: self.entities.iter(),
+            marker: PhantomData,
+        }
+    }
+    pub fn get<'q>(&'q self, entity: &EntityId) -> Option<<Q as FetchData<'w, 'q>>::Item>
+    where
+        Q: FetchData<'w, 'q> + ReadOnlyQuery,
+    {
+        if !self.contains(entity) {
+            return None;
+        }
+        let state = &self.state as *const Q::State as *mut Q::State;
+        // SAFETY: the entity matches and read-only queries never alias mutably.
+        Some(unsafe { Q::fetch(state, entity) })
+    }
+    pub fn get_mut<'q>(&'q mut self, entity: &EntityId) -> Option<<Q as FetchData<'w, 'q>>::Item>
+    where
+        Q: FetchData<'w, 'q>,
+    {
+        if !self.contains(entity) {
+            return None;
+        }
+        // SAFETY: the entity matches and the item borrows the whole query.
+        Some(unsafe { Q::fetch(&mut self.state, entity) })
+    }
+}
+
+impl<'w, 'q, Q: FetchData<'w, 'q>> Iterator for QueryIter<'w, 'q, Q> {
+    type Item = <Q as FetchData<'w, 'q>>::Item;
+    fn next(&mut self) -> Option<Self::Item> {
+        let entity = self.entities.next()?;
+        // SAFETY: the queried entities are distinct and all matched, so no
+        // two items point to the same component.
+        Some(unsafe { Q::fetch(self.state, entity) })
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.entities.size_hint()
+    }
+}
+
+impl<'w, 'q, Q: FetchData<'w, 'q>, F: QueryFilter<'w>> IntoIterator for &'q mut Query<'w, Q, F> {
+    type Item = <Q as FetchData<'w, 'q>>::Item;
+    type IntoIter = QueryIter<'w, 'q, Q>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+#[test]
+fn query_tuples_and_filters() {
+    struct Position(i32);
+    struct Velocity(i32);
+    struct Frozen;
+
+    let mut world = World::new();
+
+    let moving = world.create_entity();
+    world.insert(&moving, Position(0));
+    world.insert(&moving, Velocity(2));
+
+    let frozen = world.create_entity();
+    world.insert(&frozen, Position(10));
+    world.insert(&frozen, Velocity(5));
+    world.insert(&frozen, Frozen);
+
+    let still = world.create_entity();
+    world.insert(&still, Position(20));
+
+    for (_, position, velocity) in world
+        .query_filtered::<(&mut Position, &Velocity), Without<Frozen>>()
+        .iter_mut()
+    {
+        position.0 += velocity.0;
+    }
+
+    let mut positions = world
+        .query::<(&Position, Option<&Velocity>)>()
+        .iter()
+        .map(|(entity, position, velocity)| (entity, position.0, velocity.map(|v| v.0)))
+        .collect::<Vec<_>>();
+    positions.sort();
+
+    assert_eq!(
+        positions,
+        vec![
+            (moving.clone(), 2, Some(2)),
+            (frozen.clone(), 10, Some(5)),
+            (still, 20, None)
+        ],
+        "incorrectly queried tuples"
+    );
+
+    let frozen_query = world.query_filtered::<&Position, With<Frozen>>();
+    assert_eq!(frozen_query.entities(), &[frozen.clone()][..]);
+    assert_eq!(frozen_query.get(&frozen).map(|(_, p)| p.0), Some(10));
+    assert!(frozen_query.get(&moving).is_none());
+}
+
+#[test]
+#[should_panic]
+fn query_conflicting_terms() {
+    let mut world = World::new();
+    let alice = world.create_entity();
+    world.insert(&alice, 1u32);
+
+    world.query::<(&mut u32, &u32)>();
+}
+
+#[test]
+fn query_filters_on_queried_components() {
+    struct Health(i32);
+    struct Shield;
+
+    let mut world = World::new();
+    let shielded = world.create_entity();
+    world.insert(&shielded, Health(1));
+    world.insert(&shielded, Shield);
+    let exposed = world.create_entity();
+    world.insert(&exposed, Health(2));
+
+    assert_eq!(world.query_filtered::<&Health, With<Health>>().len(), 2);
+    assert_eq!(world.query_filtered::<&mut Health, Changed<Health>>().len(), 2);
+    assert!(world
+        .query_filtered::<&mut Health, Without<Health>>()
+        .is_empty());
+
+    let exposed_query = world.query_filtered::<(&Health, Option<&mut Shield>), Without<Shield>>();
+    assert_eq!(exposed_query.entities(), &[exposed.clone()][..]);
+    assert!(exposed_query.contains(&exposed));
+    assert!(!exposed_query.contains(&shielded));
+
+    let healths = world.query::<(&Health, &Health)>();
+    let total = healths.iter().map(|(_, a, b)| a.0 + b.0).sum::<i32>();
+    assert_eq!(total, 6);
+}
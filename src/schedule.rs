@@ -0,0 +1,513 @@
+use std::fmt;
+use std::sync::Arc;
+
+use crate::change::SystemTicks;
+use crate::lock_order::{self, Origin};
+use crate::system::{BoxedSystem, IntoSystem};
+use crate::work::{Access, Condition, Conditions, Run, Scheduler};
+
+/// The stages a `Schedule` runs, in this order. `Startup` only runs the
+/// first time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Stage {
+    Startup,
+    PreUpdate,
+    Update,
+    PostUpdate,
+    Render,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 5] = [
+        Stage::Startup,
+        Stage::PreUpdate,
+        Stage::Update,
+        Stage::PostUpdate,
+        Stage::Render,
+    ];
+}
+
+/// A system along with its label, ordering constraints and `Access`.
+/// Without an `Access` the system keeps the one of its parameters, a plain
+/// `fn(&W)` is taken as exclusive and runs alone.
+pub struct SystemConfig<W> {
+    system: BoxedSystem<W>,
+    access: Access,
+    label: Option<&'static str>,
+    before: Vec<&'static str>,
+    after: Vec<&'static str>,
+    sets: Vec<&'static str>,
+    conditions: Conditions<W>,
+}
+
+/// A named group of systems sharing run conditions and ordering
+/// constraints. Ordering against the name of a set orders against every
+/// system in it.
+pub struct SystemSet<W> {
+    name: &'static str,
+    before: Vec<&'static str>,
+    after: Vec<&'static str>,
+    conditions: Conditions<W>,
+}
+
+/// Lets a crate add its systems to a `Schedule` it doesn't own.
+pub trait Plugin<W> {
+    fn build(&self, schedule: Schedule<W>) -> Schedule<W>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScheduleError {
+    /// The systems, in order, of a cycle of ordering constraints.
+    Cycle { stage: Stage, systems: Vec<String> },
+    /// An ordering constraint names a label no system has.
+    UnknownLabel {
+        stage: Stage,
+        system: String,
+        label: &'static str,
+    },
+}
+
+/// Systems grouped in stages. Inside a stage systems are sorted by their
+/// `before`/`after` constraints, then batched by `Access` like in a
+/// `Scheduler`.
+pub struct Schedule<W> {
+    stages: Vec<(Stage, Vec<SystemConfig<W>>)>,
+    sets: Vec<SystemSet<W>>,
+    sync_point: Option<(BoxedSystem<W>, SystemTicks)>,
+    built: Option<Vec<(Stage, Scheduler<W>)>>,
+    started: bool,
+    runner: u64,
+}
+
+impl<W> SystemConfig<W> {
+    pub fn new<M, S: IntoSystem<W, M>>(system: S) -> SystemConfig<W> {
+        let system = system.into_system();
+        SystemConfig {
+            access: system.access().clone(),
+            system,
+            label: None,
+            before: vec![],
+            after: vec![],
+            sets: vec![],
+            conditions: vec![],
+        }
+    }
+    /// Several systems can share a label, constraints apply to all of them.
+    pub fn label(mut self, label: &'static str) -> Self {
+        self.label = Some(label);
+        self
+    }
+    pub fn before(mut self, label: &'static str) -> Self {
+        self.before.push(label);
+        self
+    }
+    pub fn after(mut self, label: &'static str) -> Self {
+        self.after.push(label);
+        self
+    }
+    /// Declares what a plain `fn(&W)` locks, or what a system locks besides
+    /// its parameters, whose locks are kept.
+    pub fn access(mut self, access: Access) -> Self {
+        self.access = access;
+        self.access.merge_derived(self.system.access());
+        self
+    }
+    /// The system only runs while every one of its conditions, and the
+    /// ones of its sets, holds.
+    pub fn run_if<C: Condition<W> + 'static>(mut self, condition: C) -> Self {
+        self.conditions.push(Arc::new(condition));
+        self
+    }
+    pub fn in_set(mut self, set: &'static str) -> Self {
+        self.sets.push(set);
+        self
+    }
+    fn has_label(&self, label: &'static str) -> bool {
+        self.label == Some(label) || self.sets.contains(&label)
+    }
+    fn name(&self, index: usize) -> String {
+        match self.label {
+            Some(label) => label.to_string(),
+            None => format!("system #{}", index),
+        }
+    }
+}
+
+impl<W> SystemSet<W> {
+    pub fn new(name: &'static str) -> SystemSet<W> {
+        SystemSet {
+            name,
+            before: vec![],
+            after: vec![],
+            conditions: vec![],
+        }
+    }
+    pub fn before(mut self, label: &'static str) -> Self {
+        self.before.push(label);
+        self
+    }
+    pub fn after(mut self, label: &'static str) -> Self {
+        self.after.push(label);
+        self
+    }
+    pub fn run_if<C: Condition<W> + 'static>(mut self, condition: C) -> Self {
+        self.conditions.push(Arc::new(condition));
+        self
+    }
+}
+
+impl fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScheduleError::Cycle { stage, systems } => write!(
+                f,
+                "ordering constraints of the {:?} stage form a cycle: {} -> {}",
+                stage,
+                systems.join(" -> "),
+                systems[0]
+            ),
+            ScheduleError::UnknownLabel {
+                stage,
+                system,
+                label,
+            } => write!(
+                f,
+                "{} in the {:?} stage is ordered against {}, which no system has as label",
+                system, stage, label
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ScheduleError {}
+
+impl<W: Sync> Default for Schedule<W> {
+    fn default() -> Self {
+        Schedule::new()
+    }
+}
+
+impl<W: Sync> Schedule<W> {
+    pub fn new() -> Schedule<W> {
+        Schedule {
+            stages: Stage::ALL.iter().map(|&stage| (stage, vec![])).collect(),
+            sets: vec![],
+            sync_point: None,
+            built: None,
+            started: false,
+            runner: lock_order::next_runner(),
+        }
+    }
+    pub fn add_system(mut self, stage: Stage, system: SystemConfig<W>) -> Self {
+        self.stages[stage as usize].1.push(system);
+        self.built = None;
+        self
+    }
+    /// Sets without a configuration work too, as plain shared labels.
+    pub fn configure_set(mut self, set: SystemSet<W>) -> Self {
+        self.sets.push(set);
+        self.built = None;
+        self
+    }
+    pub fn add_plugin<P: Plugin<W>>(self, plugin: P) -> Self {
+        plugin.build(self)
+    }
+    /// Runs after every stage, e.g. `LockedWorld::apply_commands`.
+    pub fn sync_point<M, S: IntoSystem<W, M>>(mut self, system: S) -> Self {
+        self.sync_point = Some((system.into_system(), SystemTicks::default()));
+        self
+    }
+    /// Sorts the systems of every stage. `run` does it on its own, this is
+    /// for checking the constraints without panicking.
+    pub fn build(&mut self) -> Result<(), ScheduleError> {
+        let mut built = vec![];
+        for (stage, systems) in &self.stages {
+            built.push((*stage, self.build_stage(*stage, systems)?));
+        }
+        self.built = Some(built);
+        Ok(())
+    }
+    fn has_label(&self, label: &'static str) -> bool {
+        self.stages
+            .iter()
+            .flat_map(|(_, systems)| systems)
+            .any(|system| system.has_label(label))
+    }
+    fn sets_of<'a>(&'a self, system: &'a SystemConfig<W>) -> impl Iterator<Item = &'a SystemSet<W>> {
+        self.sets.iter().filter(move |set| system.sets.contains(&set.name))
+    }
+    fn build_stage(
+        &self,
+        stage: Stage,
+        systems: &[SystemConfig<W>],
+    ) -> Result<Scheduler<W>, ScheduleError> {
+        let labeled = |label: &'static str| {
+            (0..systems.len()).filter(move |&i| systems[i].has_label(label))
+        };
+        // dependencies[i] holds the systems that go before system i.
+        let mut dependencies = vec![vec![]; systems.len()];
+        for (i, system) in systems.iter().enumerate() {
+            let after = self
+                .sets_of(system)
+                .flat_map(|set| &set.after)
+                .chain(&system.after)
+                .copied()
+                .collect::<Vec<_>>();
+            let before = self
+                .sets_of(system)
+                .flat_map(|set| &set.before)
+                .chain(&system.before)
+                .copied()
+                .collect::<Vec<_>>();
+            for &label in after.iter().chain(&before) {
+                // Labels of other stages are already ordered by the stages.
+                if labeled(label).next().is_none() && !self.has_label(label) {
+                    return Err(ScheduleError::UnknownLabel {
+                        stage,
+                        system: system.name(i),
+                        label,
+                    });
+                }
+            }
+            for &label in &after {
+                dependencies[i].extend(labeled(label).filter(|&other| other != i));
+            }
+            for &label in &before {
+                for other in labeled(label).filter(|&other| other != i) {
+                    dependencies[other].push(i);
+                }
+            }
+        }
+
+        let mut order = vec![];
+        let mut placed = vec![false; systems.len()];
+        while order.len() < systems.len() {
+            let next = (0..systems.len()).find(|&i| {
+                !placed[i] && dependencies[i].iter().all(|&dependency| placed[dependency])
+            });
+            match next {
+                Some(i) => {
+                    placed[i] = true;
+                    order.push(i);
+                }
+                None => {
+                    return Err(ScheduleError::Cycle {
+                        stage,
+                        systems: find_cycle(&dependencies, &placed)
+                            .into_iter()
+                            .map(|i| systems[i].name(i))
+                            .collect(),
+                    })
+                }
+            }
+        }
+
+        let mut scheduler = Scheduler::with_runner(self.runner, stage as usize);
+        let mut positions = vec![0; systems.len()];
+        for i in order {
+            let after = dependencies[i]
+                .iter()
+                .map(|&dependency| positions[dependency])
+                .collect::<Vec<_>>();
+            let system = &systems[i];
+            let conditions = self
+                .sets_of(system)
+                .flat_map(|set| &set.conditions)
+                .chain(&system.conditions)
+                .cloned()
+                .collect();
+            positions[i] = scheduler.push(system.system.clone(), system.access.clone(), &after, conditions);
+        }
+        Ok(scheduler)
+    }
+    /// Runs `Startup` the first time, then every other stage in order.
+    /// Panics if the ordering constraints can't be met.
+    pub fn run(&mut self, world: &W) {
+        if self.built.is_none() {
+            if let Err(error) = self.build() {
+                panic!("{}", error);
+            }
+        }
+        let started = std::mem::replace(&mut self.started, true);
+        for (stage, scheduler) in self.built.as_ref().unwrap() {
+            if *stage == Stage::Startup && started {
+                continue;
+            }
+            scheduler.run(world);
+            if let Some((system, ticks)) = &self.sync_point {
+                // alone between two stages, like a batch of its own
+                Origin::new(self.runner, (*stage as usize, usize::MAX), 0)
+                    .run(|| ticks.run(|| system.run(world)));
+            }
+        }
+        if !started {
+            // startup systems never run again, they mustn't hold back the
+            // removals the other stages already saw
+            self.built
+                .as_mut()
+                .unwrap()
+                .retain(|(stage, _)| *stage != Stage::Startup);
+        }
+    }
+}
+
+impl<W: Sync> Run<W> for Schedule<W> {
+    fn run(&mut self, world: &W) {
+        Schedule::run(self, world);
+    }
+}
+
+/// Walks back the unplaced dependencies, every unplaced system has one, until
+/// a system repeats.
+fn find_cycle(dependencies: &[Vec<usize>], placed: &[bool]) -> Vec<usize> {
+    let mut path = vec![placed.iter().position(|placed| !placed).unwrap()];
+    loop {
+        let last = *path.last().unwrap();
+        let next = *dependencies[last]
+            .iter()
+            .find(|&&dependency| !placed[dependency])
+            .unwrap();
+        if let Some(start) = path.iter().position(|&i| i == next) {
+            let mut cycle = path.split_off(start);
+            cycle.reverse();
+            return cycle;
+        }
+        path.push(next);
+    }
+}
+
+#[test]
+fn schedule_orders_stages_and_systems() {
+    use parking_lot::Mutex;
+
+    type Log = Mutex<Vec<&'static str>>;
+
+    struct Input;
+    impl Plugin<Log> for Input {
+        fn build(&self, schedule: Schedule<Log>) -> Schedule<Log> {
+            schedule.add_system(
+                Stage::Update,
+                SystemConfig::new(|log: &Log| log.lock().push("input")).label("input"),
+            )
+        }
+    }
+
+    let mut schedule = Schedule::new()
+        .add_system(Stage::Render, SystemConfig::new(|log: &Log| log.lock().push("draw")).label("draw"))
+        .add_system(
+            Stage::Update,
+            SystemConfig::new(|log: &Log| log.lock().push("move"))
+                .label("move")
+                .after("input")
+                .access(Access::new()),
+        )
+        .add_system(
+            Stage::Update,
+            SystemConfig::new(|log: &Log| log.lock().push("collide"))
+                .after("move")
+                .before("draw")
+                .access(Access::new()),
+        )
+        .add_system(Stage::Startup, SystemConfig::new(|log: &Log| log.lock().push("load")))
+        .add_plugin(Input)
+        .sync_point(|log: &Log| log.lock().push("|"));
+
+    let log = Log::default();
+    schedule.run(&log);
+    schedule.run(&log);
+    assert_eq!(
+        log.lock().join(" "),
+        "load | | input move collide | | draw | | input move collide | | draw |"
+    );
+}
+
+#[test]
+fn schedule_reports_cycles() {
+    fn noop(_: &()) {}
+
+    let mut schedule = Schedule::new()
+        .add_system(Stage::Update, SystemConfig::new(noop).label("a").after("c"))
+        .add_system(Stage::Update, SystemConfig::new(noop).label("b").after("a"))
+        .add_system(Stage::Update, SystemConfig::new(noop).label("c").after("b"));
+    assert_eq!(
+        schedule.build().unwrap_err().to_string(),
+        "ordering constraints of the Update stage form a cycle: b -> c -> a -> b"
+    );
+
+    let mut schedule = Schedule::new()
+        .add_system(Stage::Update, SystemConfig::new(noop).after("missing"));
+    assert!(matches!(
+        schedule.build(),
+        Err(ScheduleError::UnknownLabel { label: "missing", .. })
+    ));
+}
+
+#[test]
+fn conditions_skip_systems_and_sets() {
+    use crate::work::{resource_equals, resource_exists};
+    use crate::world::World;
+
+    #[derive(PartialEq)]
+    struct Paused(bool);
+    struct Boss;
+    #[derive(Default)]
+    struct Log(Vec<&'static str>);
+
+    let mut schedule = Schedule::new()
+        .configure_set(
+            SystemSet::new("gameplay")
+                .after("input")
+                .run_if(resource_equals(Paused(false))),
+        )
+        .add_system(
+            Stage::Update,
+            SystemConfig::new(|world: &World| world.resource_mut::<Log>().unwrap().0.push("boss"))
+                .in_set("gameplay")
+                .run_if(resource_exists::<Boss>()),
+        )
+        .add_system(
+            Stage::Update,
+            SystemConfig::new(|world: &World| world.resource_mut::<Log>().unwrap().0.push("move"))
+                .in_set("gameplay"),
+        )
+        .add_system(
+            Stage::Update,
+            SystemConfig::new(|world: &World| world.resource_mut::<Log>().unwrap().0.push("input"))
+                .label("input"),
+        );
+
+    let mut world = World::new();
+    world.create_resource(Log::default());
+    world.create_resource(Paused(false));
+    schedule.run(&world);
+    world.create_resource(Boss);
+    schedule.run(&world);
+    world.insert_resource(Paused(true));
+    schedule.run(&world);
+
+    assert_eq!(
+        world.resource::<Log>().unwrap().0,
+        ["input", "move", "input", "boss", "move", "input"]
+    );
+}
+
+#[test]
+fn sync_points_take_typed_systems() {
+    use crate::system::ResMut;
+    use crate::world::World;
+
+    #[derive(Default)]
+    struct Syncs(u32);
+
+    fn sync(mut syncs: ResMut<Syncs>) {
+        syncs.0 += 1;
+    }
+
+    let mut schedule = Schedule::new().sync_point(sync);
+    let mut world = World::new();
+    world.create_resource(Syncs::default());
+    schedule.run(&world);
+    schedule.run(&world);
+    // after every stage, `Startup` only the first time
+    assert_eq!(world.resource::<Syncs>().unwrap().0, 9);
+}
@@ -0,0 +1,481 @@
+use std::any::Any;
+use std::fmt;
+
+use hashbrown::HashMap;
+use serde::de::{self, DeserializeOwned, DeserializeSeed, MapAccess, SeqAccess, Visitor};
+use serde::ser::{SerializeMap, SerializeSeq, SerializeStruct};
+use serde::{Deserializer, Serialize, Serializer};
+
+use crate::world::{ComponentListRef, EntityId, ResourceRef, World};
+
+/// Maps the entity ids of a snapshot to the ones created when loading it.
+pub type EntityMap = HashMap<EntityId, EntityId>;
+
+/// Components holding entity ids implement this so the ids are remapped to
+/// the entities created when loading a snapshot.
+pub trait MapEntities {
+    fn map_entities(&mut self, mapper: &mut EntityMapper);
+}
+
+pub struct EntityMapper<'a> {
+    world: &'a World,
+    map: &'a mut EntityMap,
+}
+
+type SerializeFn = for<'w> fn(&'w World) -> Option<Box<dyn erased_serde::Serialize + 'w>>;
+type LoadFn = fn(
+    &mut dyn erased_serde::Deserializer,
+    &mut World,
+    &mut EntityMap,
+) -> Result<(), erased_serde::Error>;
+
+struct Registration {
+    name: &'static str,
+    serialize: SerializeFn,
+    load: LoadFn,
+}
+
+/// The components and resources that opted in to serialization, each
+/// under a name that has to stay the same between saving and loading.
+#[derive(Default)]
+pub struct TypeRegistry {
+    components: Vec<Registration>,
+    resources: Vec<Registration>,
+}
+
+pub struct WorldSnapshot<'a> {
+    registry: &'a TypeRegistry,
+    world: &'a World,
+}
+
+struct ComponentsSnapshot<'w, T: Any + Send + Sync>(ComponentListRef<'w, T>);
+
+struct ResourceSnapshot<'w, T: Any + Send + Sync>(ResourceRef<'w, T>);
+
+struct SnapshotSeed<'a> {
+    registry: &'a TypeRegistry,
+    world: &'a mut World,
+    map: &'a mut EntityMap,
+}
+
+struct TableSeed<'a> {
+    registrations: &'a [Registration],
+    kind: &'static str,
+    world: &'a mut World,
+    map: &'a mut EntityMap,
+}
+
+struct RegistrationSeed<'a> {
+    registration: &'a Registration,
+    world: &'a mut World,
+    map: &'a mut EntityMap,
+}
+
+struct EntitiesSeed<'a> {
+    world: &'a World,
+    map: &'a mut EntityMap,
+}
+
+impl<'a> EntityMapper<'a> {
+    /// The entity `entity` maps to, created if it wasn't seen yet.
+    pub fn map(&mut self, entity: &EntityId) -> EntityId {
+        let world = self.world;
+        self.map
+            .entry(entity.clone())
+            .or_insert_with(|| world.create_entity())
+            .clone()
+    }
+}
+
+impl MapEntities for EntityId {
+    fn map_entities(&mut self, mapper: &mut EntityMapper) {
+        *self = mapper.map(self);
+    }
+}
+
+impl<T: MapEntities> MapEntities for Option<T> {
+    fn map_entities(&mut self, mapper: &mut EntityMapper) {
+        if let Some(value) = self {
+            value.map_entities(mapper);
+        }
+    }
+}
+
+impl<T: MapEntities> MapEntities for Vec<T> {
+    fn map_entities(&mut self, mapper: &mut EntityMapper) {
+        for value in self {
+            value.map_entities(mapper);
+        }
+    }
+}
+
+impl<'w, T: Any + Send + Sync + Serialize> Serialize for ComponentsSnapshot<'w, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.0.len()))?;
+        for (entity, component) in self.0.iter() {
+            seq.serialize_element(&(entity.to_bits(), component))?;
+        }
+        seq.end()
+    }
+}
+
+impl<'w, T: Any + Send + Sync + Serialize> Serialize for ResourceSnapshot<'w, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        (*self.0).serialize(serializer)
+    }
+}
+
+fn serialize_components<T: Any + Send + Sync + Serialize>(
+    world: &World,
+) -> Option<Box<dyn erased_serde::Serialize + '_>> {
+    let list = world.components::<T>()?;
+    Some(Box::new(ComponentsSnapshot(list)))
+}
+
+fn serialize_resource<T: Any + Send + Sync + Serialize>(
+    world: &World,
+) -> Option<Box<dyn erased_serde::Serialize + '_>> {
+    let resource = world.resource::<T>()?;
+    Some(Box::new(ResourceSnapshot(resource)))
+}
+
+fn load_components<T: Any + Send + Sync + DeserializeOwned>(
+    deserializer: &mut dyn erased_serde::Deserializer,
+    world: &mut World,
+    map: &mut EntityMap,
+) -> Result<(), erased_serde::Error> {
+    let components: Vec<(u64, T)> = erased_serde::deserialize(deserializer)?;
+    for (entity, component) in components {
+        let entity = EntityMapper { world, map }.map(&EntityId::from(entity));
+        world.insert(&entity, component);
+    }
+    Ok(())
+}
+
+fn load_mapped_components<T: Any + Send + Sync + DeserializeOwned + MapEntities>(
+    deserializer: &mut dyn erased_serde::Deserializer,
+    world: &mut World,
+    map: &mut EntityMap,
+) -> Result<(), erased_serde::Error> {
+    let components: Vec<(u64, T)> = erased_serde::deserialize(deserializer)?;
+    for (entity, mut component) in components {
+        let mut mapper = EntityMapper { world, map };
+        let entity = mapper.map(&EntityId::from(entity));
+        component.map_entities(&mut mapper);
+        world.insert(&entity, component);
+    }
+    Ok(())
+}
+
+fn load_resource<T: Any + Send + Sync + DeserializeOwned>(
+    deserializer: &mut dyn erased_serde::Deserializer,
+    world: &mut World,
+    _: &mut EntityMap,
+) -> Result<(), erased_serde::Error> {
+    let resource: T = erased_serde::deserialize(deserializer)?;
+    world.insert_resource(resource);
+    Ok(())
+}
+
+impl TypeRegistry {
+    pub fn new() -> TypeRegistry {
+        TypeRegistry::default()
+    }
+    fn check_name(&self, name: &'static str) {
+        assert!(
+            !self
+                .components
+                .iter()
+                .chain(&self.resources)
+                .any(|registration| registration.name == name),
+            "{} is already registered",
+            name
+        );
+    }
+    pub fn register_component<T: Any + Send + Sync + Serialize + DeserializeOwned>(
+        &mut self,
+        name: &'static str,
+    ) -> &mut Self {
+        self.check_name(name);
+        self.components.push(Registration {
+            name,
+            serialize: serialize_components::<T>,
+            load: load_components::<T>,
+        });
+        self
+    }
+    /// Same as `register_component`, for components holding entity ids.
+    pub fn register_component_with_entities<
+        T: Any + Send + Sync + Serialize + DeserializeOwned + MapEntities,
+    >(
+        &mut self,
+        name: &'static str,
+    ) -> &mut Self {
+        self.check_name(name);
+        self.components.push(Registration {
+            name,
+            serialize: serialize_components::<T>,
+            load: load_mapped_components::<T>,
+        });
+        self
+    }
+    pub fn register_resource<T: Any + Send + Sync + Serialize + DeserializeOwned>(
+        &mut self,
+        name: &'static str,
+    ) -> &mut Self {
+        self.check_name(name);
+        self.resources.push(Registration {
+            name,
+            serialize: serialize_resource::<T>,
+            load: load_resource::<T>,
+        });
+        self
+    }
+    /// Serializes the entities of `world` along with its registered
+    /// components and resources. Locks them while serializing.
+    pub fn snapshot<'a>(&'a self, world: &'a World) -> WorldSnapshot<'a> {
+        WorldSnapshot {
+            registry: self,
+            world,
+        }
+    }
+    /// Spawns the entities of a snapshot into `world` and returns which
+    /// entity each one of the snapshot became.
+    pub fn load<'de, D: Deserializer<'de>>(
+        &self,
+        world: &mut World,
+        deserializer: D,
+    ) -> Result<EntityMap, D::Error> {
+        let mut map = EntityMap::new();
+        SnapshotSeed {
+            registry: self,
+            world,
+            map: &mut map,
+        }
+        .deserialize(deserializer)?;
+        Ok(map)
+    }
+}
+
+fn serialize_table<S: Serializer>(
+    serializer: S,
+    registrations: &[Registration],
+    world: &World,
+) -> Result<S::Ok, S::Error> {
+    let values = registrations
+        .iter()
+        .filter_map(|registration| Some((registration.name, (registration.serialize)(world)?)))
+        .collect::<Vec<_>>();
+    let mut map = serializer.serialize_map(Some(values.len()))?;
+    for (name, value) in &values {
+        map.serialize_entry(name, value)?;
+    }
+    map.end()
+}
+
+struct Table<'a> {
+    registrations: &'a [Registration],
+    world: &'a World,
+}
+
+impl<'a> Serialize for Table<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_table(serializer, self.registrations, self.world)
+    }
+}
+
+impl<'a> Serialize for WorldSnapshot<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let entities = self
+            .world
+            .alive_entities()
+            .iter()
+            .map(EntityId::to_bits)
+            .collect::<Vec<_>>();
+        let mut snapshot = serializer.serialize_struct("WorldSnapshot", 3)?;
+        snapshot.serialize_field("entities", &entities)?;
+        snapshot.serialize_field(
+            "components",
+            &Table {
+                registrations: &self.registry.components,
+                world: self.world,
+            },
+        )?;
+        snapshot.serialize_field(
+            "resources",
+            &Table {
+                registrations: &self.registry.resources,
+                world: self.world,
+            },
+        )?;
+        snapshot.end()
+    }
+}
+
+const FIELDS: &[&str] = &["entities", "components", "resources"];
+
+impl<'a, 'de> DeserializeSeed<'de> for SnapshotSeed<'a> {
+    type Value = ();
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_struct("WorldSnapshot", FIELDS, self)
+    }
+}
+
+impl<'a, 'de> Visitor<'de> for SnapshotSeed<'a> {
+    type Value = ();
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a world snapshot")
+    }
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        seq.next_element_seed(EntitiesSeed {
+            world: self.world,
+            map: self.map,
+        })?
+        .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        seq.next_element_seed(TableSeed {
+            registrations: &self.registry.components,
+            kind: "component",
+            world: self.world,
+            map: self.map,
+        })?
+        .ok_or_else(|| de::Error::invalid_length(1, &"a world snapshot"))?;
+        seq.next_element_seed(TableSeed {
+            registrations: &self.registry.resources,
+            kind: "resource",
+            world: self.world,
+            map: self.map,
+        })?
+        .ok_or_else(|| de::Error::invalid_length(2, &"a world snapshot"))?;
+        Ok(())
+    }
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "entities" => map.next_value_seed(EntitiesSeed {
+                    world: self.world,
+                    map: self.map,
+                })?,
+                "components" => map.next_value_seed(TableSeed {
+                    registrations: &self.registry.components,
+                    kind: "component",
+                    world: self.world,
+                    map: self.map,
+                })?,
+                "resources" => map.next_value_seed(TableSeed {
+                    registrations: &self.registry.resources,
+                    kind: "resource",
+                    world: self.world,
+                    map: self.map,
+                })?,
+                other => return Err(de::Error::unknown_field(other, FIELDS)),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a, 'de> DeserializeSeed<'de> for EntitiesSeed<'a> {
+    type Value = ();
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        let entities: Vec<u64> = serde::Deserialize::deserialize(deserializer)?;
+        let mut mapper = EntityMapper {
+            world: self.world,
+            map: self.map,
+        };
+        for entity in entities {
+            mapper.map(&EntityId::from(entity));
+        }
+        Ok(())
+    }
+}
+
+impl<'a, 'de> DeserializeSeed<'de> for TableSeed<'a> {
+    type Value = ();
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'a, 'de> Visitor<'de> for TableSeed<'a> {
+    type Value = ();
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a map of {}s", self.kind)
+    }
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        while let Some(name) = map.next_key::<String>()? {
+            let registration = self
+                .registrations
+                .iter()
+                .find(|registration| registration.name == name)
+                .ok_or_else(|| {
+                    de::Error::custom(format!("{} {} is not registered", self.kind, name))
+                })?;
+            map.next_value_seed(RegistrationSeed {
+                registration,
+                world: self.world,
+                map: self.map,
+            })?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a, 'de> DeserializeSeed<'de> for RegistrationSeed<'a> {
+    type Value = ();
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        let mut deserializer = <dyn erased_serde::Deserializer>::erase(deserializer);
+        (self.registration.load)(&mut deserializer, self.world, self.map).map_err(de::Error::custom)
+    }
+}
+
+#[test]
+fn snapshot_round_trip() {
+    use serde::Deserialize;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Health(i32);
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Target(EntityId);
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Score(u32);
+
+    impl MapEntities for Target {
+        fn map_entities(&mut self, mapper: &mut EntityMapper) {
+            self.0.map_entities(mapper);
+        }
+    }
+
+    let mut registry = TypeRegistry::new();
+    registry
+        .register_component::<Health>("Health")
+        .register_component_with_entities::<Target>("Target")
+        .register_resource::<Score>("Score");
+
+    let mut world = World::new();
+    let alice = world.create_entity();
+    let bob = world.create_entity();
+    let empty = world.create_entity();
+    world.insert(&alice, Health(3));
+    world.insert(&alice, Target(bob.clone()));
+    world.insert(&bob, Health(1));
+    world.create_resource(Score(7));
+
+    let json = serde_json::to_string(&registry.snapshot(&world)).unwrap();
+
+    let mut loaded = World::new();
+    loaded.create_entity();
+    let map = registry
+        .load(&mut loaded, &mut serde_json::Deserializer::from_str(&json))
+        .unwrap();
+
+    let (alice, bob) = (&map[&alice], &map[&bob]);
+    assert!(loaded.is_alive(&map[&empty]), "entity without components was lost");
+    assert_eq!(loaded.components::<Health>().unwrap().get(alice), Some(&Health(3)));
+    assert_eq!(loaded.components::<Health>().unwrap().get(bob), Some(&Health(1)));
+    assert_eq!(
+        loaded.components::<Target>().unwrap().get(alice),
+        Some(&Target(bob.clone())),
+        "entity reference was not remapped"
+    );
+    assert_eq!(*loaded.resource::<Score>().unwrap(), Score(7));
+}
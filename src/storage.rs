@@ -0,0 +1,653 @@
+use std::iter::Zip;
+use std::mem::size_of;
+use std::ptr::NonNull;
+use std::slice::{Iter, IterMut};
+
+use hashbrown::HashMap;
+
+use crate::world::EntityId;
+
+const EMPTY: u32 = u32::MAX;
+
+/// How the components of a type are laid out, chosen per type with
+/// `World::register_storage`. Zero sized types default to `Tag`, the rest
+/// to `SparseSet`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StorageType {
+    /// Dense packed components, indexed through a sparse array. Fast to
+    /// iterate and to look up, the default.
+    SparseSet,
+    /// One slot per entity index, for components almost every entity has.
+    DenseVec,
+    /// A hash map, for components few entities have and that are rarely
+    /// touched.
+    HashMap,
+    /// A bitset, only for zero sized marker components.
+    Tag,
+}
+
+impl StorageType {
+    pub(crate) fn default_for<T>() -> StorageType {
+        if size_of::<T>() == 0 {
+            StorageType::Tag
+        } else {
+            StorageType::SparseSet
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum ComponentStorage<T> {
+    SparseSet(SparseSet<T>),
+    DenseVec(DenseVec<T>),
+    HashMap(HashMapStorage<T>),
+    Tag(TagSet<T>),
+}
+
+macro_rules! dispatch {
+    ($self:expr, $storage:ident => $body:expr) => {
+        match $self {
+            ComponentStorage::SparseSet($storage) => $body,
+            ComponentStorage::DenseVec($storage) => $body,
+            ComponentStorage::HashMap($storage) => $body,
+            ComponentStorage::Tag($storage) => $body,
+        }
+    };
+}
+
+impl<T> ComponentStorage<T> {
+    pub(crate) fn new(storage: StorageType) -> ComponentStorage<T> {
+        match storage {
+            StorageType::SparseSet => ComponentStorage::SparseSet(SparseSet::new()),
+            StorageType::DenseVec => ComponentStorage::DenseVec(DenseVec::new()),
+            StorageType::HashMap => ComponentStorage::HashMap(HashMapStorage::new()),
+            StorageType::Tag => ComponentStorage::Tag(TagSet::new()),
+        }
+    }
+    pub(crate) fn storage_type(&self) -> StorageType {
+        match self {
+            ComponentStorage::SparseSet(_) => StorageType::SparseSet,
+            ComponentStorage::DenseVec(_) => StorageType::DenseVec,
+            ComponentStorage::HashMap(_) => StorageType::HashMap,
+            ComponentStorage::Tag(_) => StorageType::Tag,
+        }
+    }
+    /// Moves every component into a storage of another type.
+    pub(crate) fn convert(&mut self, storage: StorageType) {
+        if self.storage_type() == storage {
+            return;
+        }
+        let old = std::mem::replace(self, ComponentStorage::new(storage));
+        for (entity, component) in dispatch!(old, storage => storage.into_vec()) {
+            self.insert(entity, component);
+        }
+    }
+    pub(crate) fn len(&self) -> usize {
+        dispatch!(self, storage => storage.len())
+    }
+    pub(crate) fn contains(&self, entity: &EntityId) -> bool {
+        dispatch!(self, storage => storage.contains(entity))
+    }
+    pub(crate) fn entities(&self) -> Vec<EntityId> {
+        match self {
+            ComponentStorage::SparseSet(storage) => storage.entities.clone(),
+            _ => self.iter().map(|(entity, _)| entity.clone()).collect(),
+        }
+    }
+    pub(crate) fn get(&self, entity: &EntityId) -> Option<&T> {
+        dispatch!(self, storage => storage.get(entity))
+    }
+    pub(crate) fn get_mut(&mut self, entity: &EntityId) -> Option<&mut T> {
+        // SAFETY: the pointer is valid and borrows `self` mutably.
+        self.get_mut_ptr(entity).map(|component| unsafe { &mut *component })
+    }
+    /// Pointer to the component that doesn't reborrow the whole storage, so
+    /// references handed out earlier for other entities stay valid.
+    pub(crate) fn get_mut_ptr(&mut self, entity: &EntityId) -> Option<*mut T> {
+        dispatch!(self, storage => storage.get_mut_ptr(entity))
+    }
+    pub(crate) fn get_many_mut<const N: usize>(
+        &mut self,
+        entities: [&EntityId; N],
+    ) -> Option<[&mut T; N]> {
+        let mut components = [NonNull::dangling().as_ptr(); N];
+        for (i, entity) in entities.iter().enumerate() {
+            if entities[..i].contains(entity) {
+                return None;
+            }
+            components[i] = self.get_mut_ptr(entity)?;
+        }
+        // SAFETY: the entities are distinct so the components don't alias.
+        Some(components.map(|component| unsafe { &mut *component }))
+    }
+    pub(crate) fn insert(&mut self, entity: EntityId, component: T) -> Option<T> {
+        dispatch!(self, storage => storage.insert(entity, component))
+    }
+    pub(crate) fn remove(&mut self, entity: &EntityId) -> Option<T> {
+        dispatch!(self, storage => storage.remove(entity))
+    }
+    /// Makes room for `additional` components of entities with indices
+    /// below `end`.
+    pub(crate) fn reserve(&mut self, additional: usize, end: usize) {
+        dispatch!(self, storage => storage.reserve(additional, end))
+    }
+    /// Removes the components `keep` returns false for, returning their
+    /// entities.
+    pub(crate) fn retain(
+        &mut self,
+        mut keep: impl FnMut(&EntityId, &T) -> bool,
+    ) -> Vec<EntityId> {
+        let removed = self
+            .iter()
+            .filter(|(entity, component)| !keep(entity, component))
+            .map(|(entity, _)| entity.clone())
+            .collect::<Vec<_>>();
+        for entity in &removed {
+            self.remove(entity);
+        }
+        removed
+    }
+    pub(crate) fn clear(&mut self) {
+        dispatch!(self, storage => storage.clear())
+    }
+    pub(crate) fn iter(&self) -> StorageIter<'_, T> {
+        match self {
+            ComponentStorage::SparseSet(storage) => {
+                StorageIter::SparseSet(storage.entities.iter().zip(storage.data.iter()))
+            }
+            ComponentStorage::DenseVec(storage) => StorageIter::DenseVec(storage.slots.iter()),
+            ComponentStorage::HashMap(storage) => StorageIter::HashMap(storage.map.iter()),
+            ComponentStorage::Tag(storage) => StorageIter::Tag(storage, 0),
+        }
+    }
+    pub(crate) fn iter_mut(&mut self) -> StorageIterMut<'_, T> {
+        match self {
+            ComponentStorage::SparseSet(storage) => StorageIterMut::SparseSet(
+                storage.entities.iter().zip(storage.data.iter_mut()),
+            ),
+            ComponentStorage::DenseVec(storage) => {
+                StorageIterMut::DenseVec(storage.slots.iter_mut())
+            }
+            ComponentStorage::HashMap(storage) => StorageIterMut::HashMap(storage.map.iter_mut()),
+            ComponentStorage::Tag(storage) => StorageIterMut::Tag(storage, 0),
+        }
+    }
+}
+
+pub(crate) enum StorageIter<'a, T> {
+    SparseSet(Zip<Iter<'a, EntityId>, Iter<'a, T>>),
+    DenseVec(Iter<'a, Option<(EntityId, T)>>),
+    HashMap(hashbrown::hash_map::Iter<'a, EntityId, T>),
+    Tag(&'a TagSet<T>, usize),
+}
+
+pub(crate) enum StorageIterMut<'a, T> {
+    SparseSet(Zip<Iter<'a, EntityId>, IterMut<'a, T>>),
+    DenseVec(IterMut<'a, Option<(EntityId, T)>>),
+    HashMap(hashbrown::hash_map::IterMut<'a, EntityId, T>),
+    Tag(&'a TagSet<T>, usize),
+}
+
+impl<'a, T> Iterator for StorageIter<'a, T> {
+    type Item = (&'a EntityId, &'a T);
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            StorageIter::SparseSet(iter) => iter.next(),
+            StorageIter::DenseVec(iter) => iter
+                .find_map(|slot| slot.as_ref())
+                .map(|(entity, component)| (entity, component)),
+            StorageIter::HashMap(iter) => iter.next(),
+            StorageIter::Tag(set, index) => {
+                let entity = set.next_from(index)?;
+                // SAFETY: `T` is zero sized.
+                Some((entity, unsafe { &*NonNull::dangling().as_ptr() }))
+            }
+        }
+    }
+}
+
+impl<'a, T> Iterator for StorageIterMut<'a, T> {
+    type Item = (&'a EntityId, &'a mut T);
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            StorageIterMut::SparseSet(iter) => iter.next(),
+            StorageIterMut::DenseVec(iter) => iter
+                .find_map(|slot| slot.as_mut())
+                .map(|(entity, component)| (&*entity, component)),
+            StorageIterMut::HashMap(iter) => iter.next(),
+            StorageIterMut::Tag(set, index) => {
+                let entity = set.next_from(index)?;
+                // SAFETY: `T` is zero sized.
+                Some((entity, unsafe { &mut *NonNull::dangling().as_ptr() }))
+            }
+        }
+    }
+}
+
+/// Components packed in a dense `Vec`, with a sparse array indexed by
+/// entity index pointing into it.
+#[derive(Debug)]
+pub(crate) struct SparseSet<T> {
+    sparse: Vec<u32>,
+    entities: Vec<EntityId>,
+    data: Vec<T>,
+}
+
+impl<T> SparseSet<T> {
+    pub(crate) fn new() -> SparseSet<T> {
+        SparseSet {
+            sparse: vec![],
+            entities: vec![],
+            data: vec![],
+        }
+    }
+    fn dense_index(&self, entity: &EntityId) -> Option<usize> {
+        let dense = *self.sparse.get(entity.index() as usize)?;
+        if dense != EMPTY && self.entities[dense as usize] == *entity {
+            Some(dense as usize)
+        } else {
+            None
+        }
+    }
+    fn len(&self) -> usize {
+        self.data.len()
+    }
+    fn contains(&self, entity: &EntityId) -> bool {
+        self.dense_index(entity).is_some()
+    }
+    fn get(&self, entity: &EntityId) -> Option<&T> {
+        self.dense_index(entity).map(|dense| &self.data[dense])
+    }
+    fn get_mut_ptr(&mut self, entity: &EntityId) -> Option<*mut T> {
+        let dense = self.dense_index(entity)?;
+        // SAFETY: `dense` is in bounds of `data`.
+        Some(unsafe { self.data.as_mut_ptr().add(dense) })
+    }
+    fn insert(&mut self, entity: EntityId, component: T) -> Option<T> {
+        let index = entity.index() as usize;
+        if index >= self.sparse.len() {
+            self.sparse.resize(index + 1, EMPTY);
+        }
+        let dense = self.sparse[index];
+        if dense != EMPTY {
+            let dense = dense as usize;
+            let previous = std::mem::replace(&mut self.data[dense], component);
+            if self.entities[dense] == entity {
+                return Some(previous);
+            }
+            self.entities[dense] = entity;
+            return None;
+        }
+        self.sparse[index] = self.data.len() as u32;
+        self.entities.push(entity);
+        self.data.push(component);
+        None
+    }
+    fn remove(&mut self, entity: &EntityId) -> Option<T> {
+        let dense = self.dense_index(entity)?;
+        self.sparse[entity.index() as usize] = EMPTY;
+        self.entities.swap_remove(dense);
+        if let Some(moved) = self.entities.get(dense) {
+            self.sparse[moved.index() as usize] = dense as u32;
+        }
+        Some(self.data.swap_remove(dense))
+    }
+    fn reserve(&mut self, additional: usize, end: usize) {
+        if end > self.sparse.len() {
+            self.sparse.resize(end, EMPTY);
+        }
+        self.entities.reserve(additional);
+        self.data.reserve(additional);
+    }
+    fn clear(&mut self) {
+        self.sparse.clear();
+        self.entities.clear();
+        self.data.clear();
+    }
+    fn into_vec(self) -> Vec<(EntityId, T)> {
+        self.entities.into_iter().zip(self.data).collect()
+    }
+}
+
+/// One slot per entity index.
+#[derive(Debug)]
+pub(crate) struct DenseVec<T> {
+    slots: Vec<Option<(EntityId, T)>>,
+    len: usize,
+}
+
+impl<T> DenseVec<T> {
+    fn new() -> DenseVec<T> {
+        DenseVec {
+            slots: vec![],
+            len: 0,
+        }
+    }
+    fn slot(&self, entity: &EntityId) -> Option<&(EntityId, T)> {
+        match self.slots.get(entity.index() as usize) {
+            Some(Some(slot)) if slot.0 == *entity => Some(slot),
+            _ => None,
+        }
+    }
+    fn len(&self) -> usize {
+        self.len
+    }
+    fn contains(&self, entity: &EntityId) -> bool {
+        self.slot(entity).is_some()
+    }
+    fn get(&self, entity: &EntityId) -> Option<&T> {
+        self.slot(entity).map(|(_, component)| component)
+    }
+    fn get_mut_ptr(&mut self, entity: &EntityId) -> Option<*mut T> {
+        let index = entity.index() as usize;
+        if index >= self.slots.len() {
+            return None;
+        }
+        // SAFETY: `index` is in bounds. Only this slot is borrowed, going
+        // through `slot` would borrow every one of them.
+        match unsafe { &mut *self.slots.as_mut_ptr().add(index) } {
+            Some((owner, component)) if owner == entity => Some(component as *mut T),
+            _ => None,
+        }
+    }
+    fn insert(&mut self, entity: EntityId, component: T) -> Option<T> {
+        let index = entity.index() as usize;
+        if index >= self.slots.len() {
+            self.slots.resize_with(index + 1, || None);
+        }
+        match self.slots[index].replace((entity.clone(), component)) {
+            Some((previous, component)) if previous == entity => Some(component),
+            Some(_) => None,
+            None => {
+                self.len += 1;
+                None
+            }
+        }
+    }
+    fn remove(&mut self, entity: &EntityId) -> Option<T> {
+        self.slot(entity)?;
+        self.len -= 1;
+        self.slots[entity.index() as usize]
+            .take()
+            .map(|(_, component)| component)
+    }
+    fn reserve(&mut self, _additional: usize, end: usize) {
+        if end > self.slots.len() {
+            self.slots.resize_with(end, || None);
+        }
+    }
+    fn clear(&mut self) {
+        self.slots.clear();
+        self.len = 0;
+    }
+    fn into_vec(self) -> Vec<(EntityId, T)> {
+        self.slots.into_iter().flatten().collect()
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct HashMapStorage<T> {
+    map: HashMap<EntityId, T>,
+}
+
+impl<T> HashMapStorage<T> {
+    fn new() -> HashMapStorage<T> {
+        HashMapStorage {
+            map: HashMap::new(),
+        }
+    }
+    fn len(&self) -> usize {
+        self.map.len()
+    }
+    fn contains(&self, entity: &EntityId) -> bool {
+        self.map.contains_key(entity)
+    }
+    fn get(&self, entity: &EntityId) -> Option<&T> {
+        self.map.get(entity)
+    }
+    fn get_mut_ptr(&mut self, entity: &EntityId) -> Option<*mut T> {
+        self.map.get_mut(entity).map(|component| component as *mut T)
+    }
+    fn insert(&mut self, entity: EntityId, component: T) -> Option<T> {
+        self.map.insert(entity, component)
+    }
+    fn remove(&mut self, entity: &EntityId) -> Option<T> {
+        self.map.remove(entity)
+    }
+    fn reserve(&mut self, additional: usize, _end: usize) {
+        self.map.reserve(additional);
+    }
+    fn clear(&mut self) {
+        self.map.clear();
+    }
+    fn into_vec(self) -> Vec<(EntityId, T)> {
+        self.map.into_iter().collect()
+    }
+}
+
+/// A bitset of the entity indices holding a zero sized component. The
+/// components themselves are never stored, every set bit owns one that is
+/// conjured back when read, removed or dropped.
+#[derive(Debug)]
+pub(crate) struct TagSet<T> {
+    bits: Vec<u64>,
+    entities: Vec<EntityId>,
+    len: usize,
+    marker: std::marker::PhantomData<T>,
+}
+
+impl<T> TagSet<T> {
+    fn new() -> TagSet<T> {
+        assert!(
+            size_of::<T>() == 0,
+            "tag storage only holds zero sized components, {} is {} bytes",
+            std::any::type_name::<T>(),
+            size_of::<T>()
+        );
+        TagSet {
+            bits: vec![],
+            entities: vec![],
+            len: 0,
+            marker: std::marker::PhantomData,
+        }
+    }
+    fn conjure() -> T {
+        // SAFETY: `T` is zero sized and every set bit owns one value.
+        unsafe { std::ptr::read(NonNull::dangling().as_ptr()) }
+    }
+    fn bit(&self, index: usize) -> bool {
+        self.bits
+            .get(index / 64)
+            .is_some_and(|word| word & (1 << (index % 64)) != 0)
+    }
+    fn next_from(&self, index: &mut usize) -> Option<&EntityId> {
+        while *index < self.entities.len() {
+            let current = *index;
+            *index += 1;
+            if self.bit(current) {
+                return Some(&self.entities[current]);
+            }
+        }
+        None
+    }
+    fn len(&self) -> usize {
+        self.len
+    }
+    fn contains(&self, entity: &EntityId) -> bool {
+        let index = entity.index() as usize;
+        self.bit(index) && self.entities[index] == *entity
+    }
+    fn get(&self, entity: &EntityId) -> Option<&T> {
+        if self.contains(entity) {
+            // SAFETY: `T` is zero sized.
+            Some(unsafe { &*NonNull::dangling().as_ptr() })
+        } else {
+            None
+        }
+    }
+    fn get_mut_ptr(&mut self, entity: &EntityId) -> Option<*mut T> {
+        if self.contains(entity) {
+            Some(NonNull::dangling().as_ptr())
+        } else {
+            None
+        }
+    }
+    fn insert(&mut self, entity: EntityId, component: T) -> Option<T> {
+        let index = entity.index() as usize;
+        if index >= self.entities.len() {
+            self.entities.resize(index + 1, entity.clone());
+            self.bits.resize(index / 64 + 1, 0);
+        }
+        std::mem::forget(component);
+        if self.bit(index) {
+            let previous = TagSet::conjure();
+            if self.entities[index] == entity {
+                return Some(previous);
+            }
+            self.entities[index] = entity;
+            return None;
+        }
+        self.bits[index / 64] |= 1 << (index % 64);
+        self.entities[index] = entity;
+        self.len += 1;
+        None
+    }
+    fn remove(&mut self, entity: &EntityId) -> Option<T> {
+        if !self.contains(entity) {
+            return None;
+        }
+        let index = entity.index() as usize;
+        self.bits[index / 64] &= !(1 << (index % 64));
+        self.len -= 1;
+        Some(TagSet::conjure())
+    }
+    fn reserve(&mut self, _additional: usize, end: usize) {
+        self.entities.reserve(end.saturating_sub(self.entities.len()));
+        self.bits.reserve(end.div_ceil(64).saturating_sub(self.bits.len()));
+    }
+    fn clear(&mut self) {
+        for _ in 0..self.len {
+            drop(TagSet::<T>::conjure());
+        }
+        self.bits.clear();
+        self.entities.clear();
+        self.len = 0;
+    }
+    fn into_vec(mut self) -> Vec<(EntityId, T)> {
+        let mut components = Vec::with_capacity(self.len);
+        let mut index = 0;
+        while let Some(entity) = self.next_from(&mut index) {
+            components.push((entity.clone(), TagSet::conjure()));
+        }
+        self.len = 0;
+        components
+    }
+}
+
+impl<T> Drop for TagSet<T> {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+#[test]
+fn storages_behave_alike() {
+    for storage in [
+        StorageType::SparseSet,
+        StorageType::DenseVec,
+        StorageType::HashMap,
+    ] {
+        let mut set = ComponentStorage::new(storage);
+
+        let ids = (0..4u64).map(EntityId::from).collect::<Vec<_>>();
+        for (i, id) in ids.iter().enumerate() {
+            assert!(set.insert(id.clone(), i).is_none());
+        }
+
+        assert_eq!(set.remove(&ids[1]), Some(1));
+        assert_eq!(set.remove(&ids[1]), None);
+        assert_eq!(set.get(&ids[3]), Some(&3), "{:?} lost a component", storage);
+        assert_eq!(set.insert(ids[3].clone(), 30), Some(3));
+
+        let stale = EntityId::from(1 << 32 | 2);
+        assert!(!set.contains(&stale), "{:?} matched a stale id", storage);
+
+        let [a, b] = set.get_many_mut([&ids[0], &ids[2]]).unwrap();
+        std::mem::swap(a, b);
+        assert!(set.get_many_mut([&ids[0], &ids[0]]).is_none());
+
+        set.convert(StorageType::SparseSet);
+        let mut components = set
+            .iter()
+            .map(|(id, c)| (id.index(), *c))
+            .collect::<Vec<_>>();
+        components.sort();
+        assert_eq!(components, vec![(0, 2), (2, 0), (3, 30)], "{:?}", storage);
+    }
+}
+
+#[test]
+fn mutable_items_stay_valid_together() {
+    use crate::world::World;
+
+    struct Tile(u32);
+
+    // writes through items collected first, which miri checks don't alias
+    for storage in [
+        StorageType::SparseSet,
+        StorageType::DenseVec,
+        StorageType::HashMap,
+    ] {
+        let mut world = World::new();
+        world.register_storage::<Tile>(storage);
+        for i in 0..4 {
+            let entity = world.create_entity();
+            world.insert(&entity, Tile(i));
+        }
+
+        let mut tiles = world.query::<&mut Tile>();
+        let items = tiles.iter_mut().collect::<Vec<_>>();
+        for (_, tile) in items {
+            tile.0 += 10;
+        }
+        drop(tiles);
+
+        let mut values = world
+            .query::<&Tile>()
+            .iter()
+            .map(|(_, tile)| tile.0)
+            .collect::<Vec<_>>();
+        values.sort();
+        assert_eq!(values, [10, 11, 12, 13], "{:?}", storage);
+    }
+}
+
+#[test]
+fn tag_storage_keeps_drops() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static DROPS: AtomicUsize = AtomicUsize::new(0);
+    struct Dead;
+    impl Drop for Dead {
+        fn drop(&mut self) {
+            DROPS.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    assert_eq!(StorageType::default_for::<Dead>(), StorageType::Tag);
+
+    let mut set = ComponentStorage::new(StorageType::Tag);
+    let ids = (0..100u64).map(EntityId::from).collect::<Vec<_>>();
+    for id in &ids {
+        set.insert(id.clone(), Dead);
+    }
+    assert_eq!(set.len(), 100);
+    assert_eq!(DROPS.load(Ordering::SeqCst), 0);
+
+    assert!(set.remove(&ids[70]).is_some());
+    assert_eq!(DROPS.load(Ordering::SeqCst), 1);
+    assert_eq!(set.iter().count(), 99);
+
+    drop(set);
+    assert_eq!(DROPS.load(Ordering::SeqCst), 100);
+}
@@ -0,0 +1,628 @@
+use std::any::{type_name, Any, TypeId};
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use crate::commands::Commands;
+use crate::event::{EventCursor, EventReader, EventWriter, Events, ReadEvents};
+use crate::non_send::{NonSendResourceMut, NonSendResourceRef};
+use crate::query::{Query, QueryData, QueryFilter};
+use crate::work::Access;
+use crate::world::{ResourceMut, ResourceRef, World, WorldAccess};
+
+/// A system ready to be run by a `Work` or a `Scheduler`, made from a
+/// function or closure taking `&W` or typed parameters. Clones share the
+/// same function and state.
+pub struct BoxedSystem<W> {
+    name: &'static str,
+    access: Access,
+    run: Arc<Mutex<SystemFn<W>>>,
+}
+
+type SystemFn<W> = dyn FnMut(&W) + Send;
+
+/// Functions that can be turned into a system. `Marker` tells apart the
+/// implementations for plain `fn(&W)` and for typed parameters.
+pub trait IntoSystem<W, Marker> {
+    fn into_system(self) -> BoxedSystem<W>;
+}
+
+/// A parameter of a system. Implemented for the parameter type with any
+/// lifetime, `Fetch` builds it for the lifetime of the locked world.
+pub trait SystemParam: Sized {
+    type Fetch: ParamState + for<'w> FetchParam<'w>;
+}
+
+/// State a parameter keeps between runs of its system.
+pub trait ParamState {
+    type State: Default + Send + 'static;
+}
+
+pub trait FetchParam<'w>: ParamState {
+    type Item;
+    fn access(access: &mut Access);
+    /// Panics, naming `system`, if what the parameter borrows is missing.
+    fn fetch(world: &'w World, state: &'w mut Self::State, system: &'static str) -> Self::Item;
+}
+
+/// Shared access to the resource `T`. The system panics if it's missing,
+/// `Option<Res<T>>` doesn't.
+pub struct Res<'w, T: Any + Send + Sync> {
+    resource: ResourceRef<'w, T>,
+}
+
+/// Exclusive access to the resource `T`.
+pub struct ResMut<'w, T: Any + Send + Sync> {
+    resource: ResourceMut<'w, T>,
+}
+
+/// Shared access to the non-send resource `T`, pinning the system to the
+/// main thread.
+pub struct NonSend<'w, T: Any> {
+    resource: NonSendResourceRef<'w, T>,
+}
+
+/// Exclusive access to the non-send resource `T`, pinning the system to
+/// the main thread.
+pub struct NonSendMut<'w, T: Any> {
+    resource: NonSendResourceMut<'w, T>,
+}
+
+/// A value owned by the system, starting as `T::default()` and kept
+/// between runs.
+pub struct Local<'s, T: Default + Send + 'static> {
+    value: &'s mut T,
+}
+
+pub struct ResFetch<T>(PhantomData<fn() -> T>);
+
+pub struct ResMutFetch<T>(PhantomData<fn() -> T>);
+
+pub struct NonSendFetch<T>(PhantomData<fn() -> T>);
+
+pub struct NonSendMutFetch<T>(PhantomData<fn() -> T>);
+
+pub struct OptionFetch<F>(PhantomData<fn() -> F>);
+
+pub struct QueryFetch<Q, F>(PhantomData<fn() -> (Q, F)>);
+
+pub struct CommandsFetch;
+
+pub struct LocalFetch<T>(PhantomData<fn() -> T>);
+
+pub struct EventReaderFetch<E>(PhantomData<fn() -> E>);
+
+pub struct EventWriterFetch<E>(PhantomData<fn() -> E>);
+
+pub struct RawMarker;
+
+pub struct ParamMarker;
+
+impl<W> Clone for BoxedSystem<W> {
+    fn clone(&self) -> Self {
+        BoxedSystem {
+            name: self.name,
+            access: self.access.clone(),
+            run: self.run.clone(),
+        }
+    }
+}
+
+impl<W> BoxedSystem<W> {
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+    /// What the system declares it locks, the whole world for a plain
+    /// `fn(&W)`.
+    pub fn access(&self) -> &Access {
+        &self.access
+    }
+    pub fn run(&self, world: &W) {
+        (self.run.lock())(world)
+    }
+}
+
+impl<W, F: FnMut(&W) + Send + 'static> IntoSystem<W, RawMarker> for F {
+    fn into_system(self) -> BoxedSystem<W> {
+        BoxedSystem {
+            name: type_name::<F>(),
+            access: Access::exclusive(),
+            run: Arc::new(Mutex::new(self)),
+        }
+    }
+}
+
+impl<W> IntoSystem<W, ()> for BoxedSystem<W> {
+    fn into_system(self) -> BoxedSystem<W> {
+        self
+    }
+}
+
+impl<'s, T: Default + Send + 'static> Deref for Local<'s, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        self.value
+    }
+}
+
+impl<'s, T: Default + Send + 'static> DerefMut for Local<'s, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.value
+    }
+}
+
+impl<'w, T: Any + Send + Sync> Deref for Res<'w, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        &self.resource
+    }
+}
+
+impl<'w, T: Any + Send + Sync> Deref for ResMut<'w, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        &self.resource
+    }
+}
+
+impl<'w, T: Any + Send + Sync> DerefMut for ResMut<'w, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.resource
+    }
+}
+
+impl<'w, T: Any> Deref for NonSend<'w, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        &self.resource
+    }
+}
+
+impl<'w, T: Any> Deref for NonSendMut<'w, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        &self.resource
+    }
+}
+
+impl<'w, T: Any> DerefMut for NonSendMut<'w, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.resource
+    }
+}
+
+fn missing_resource<T>(system: &'static str) -> ! {
+    panic!(
+        "system {} can't run: resource {} doesn't exist",
+        system,
+        type_name::<T>()
+    )
+}
+
+impl<T> ParamState for ResFetch<T> {
+    type State = ();
+}
+
+impl<T> ParamState for ResMutFetch<T> {
+    type State = ();
+}
+
+impl<T> ParamState for NonSendFetch<T> {
+    type State = ();
+}
+
+impl<T> ParamState for NonSendMutFetch<T> {
+    type State = ();
+}
+
+impl<F> ParamState for OptionFetch<F> {
+    type State = ();
+}
+
+impl<Q, F> ParamState for QueryFetch<Q, F> {
+    type State = ();
+}
+
+impl ParamState for CommandsFetch {
+    type State = ();
+}
+
+impl<T: Default + Send + 'static> ParamState for LocalFetch<T> {
+    type State = T;
+}
+
+impl<E: Any + Send + Sync> ParamState for EventReaderFetch<E> {
+    type State = EventCursor<E>;
+}
+
+impl<E> ParamState for EventWriterFetch<E> {
+    type State = ();
+}
+
+impl<'w, T: Any + Send + Sync> FetchParam<'w> for ResFetch<T> {
+    type Item = Res<'w, T>;
+    fn access(access: &mut Access) {
+        access.add_resource(TypeId::of::<T>(), type_name::<T>(), false);
+    }
+    fn fetch(world: &'w World, _: &'w mut (), system: &'static str) -> Self::Item {
+        Res {
+            resource: world
+                .resource::<T>()
+                .unwrap_or_else(|| missing_resource::<T>(system)),
+        }
+    }
+}
+
+impl<'w, T: Any + Send + Sync> FetchParam<'w> for ResMutFetch<T> {
+    type Item = ResMut<'w, T>;
+    fn access(access: &mut Access) {
+        access.add_resource(TypeId::of::<T>(), type_name::<T>(), true);
+    }
+    fn fetch(world: &'w World, _: &'w mut (), system: &'static str) -> Self::Item {
+        ResMut {
+            resource: world
+                .resource_mut::<T>()
+                .unwrap_or_else(|| missing_resource::<T>(system)),
+        }
+    }
+}
+
+impl<'w, T: Any> FetchParam<'w> for NonSendFetch<T> {
+    type Item = NonSend<'w, T>;
+    fn access(access: &mut Access) {
+        access.add_non_send(TypeId::of::<T>(), type_name::<T>(), false);
+    }
+    fn fetch(world: &'w World, _: &'w mut (), system: &'static str) -> Self::Item {
+        NonSend {
+            resource: world
+                .non_send_resource::<T>()
+                .unwrap_or_else(|| missing_resource::<T>(system)),
+        }
+    }
+}
+
+impl<'w, T: Any> FetchParam<'w> for NonSendMutFetch<T> {
+    type Item = NonSendMut<'w, T>;
+    fn access(access: &mut Access) {
+        access.add_non_send(TypeId::of::<T>(), type_name::<T>(), true);
+    }
+    fn fetch(world: &'w World, _: &'w mut (), system: &'static str) -> Self::Item {
+        NonSendMut {
+            resource: world
+                .non_send_resource_mut::<T>()
+                .unwrap_or_else(|| missing_resource::<T>(system)),
+        }
+    }
+}
+
+impl<'w, T: Any + Send + Sync> FetchParam<'w> for OptionFetch<ResFetch<T>> {
+    type Item = Option<Res<'w, T>>;
+    fn access(access: &mut Access) {
+        ResFetch::<T>::access(access);
+    }
+    fn fetch(world: &'w World, _: &'w mut (), _: &'static str) -> Self::Item {
+        world.resource::<T>().map(|resource| Res { resource })
+    }
+}
+
+impl<'w, T: Any + Send + Sync> FetchParam<'w> for OptionFetch<ResMutFetch<T>> {
+    type Item = Option<ResMut<'w, T>>;
+    fn access(access: &mut Access) {
+        ResMutFetch::<T>::access(access);
+    }
+    fn fetch(world: &'w World, _: &'w mut (), _: &'static str) -> Self::Item {
+        world.resource_mut::<T>().map(|resource| ResMut { resource })
+    }
+}
+
+impl<'w, Q: QueryData<'w>, F: QueryFilter<'w>> FetchParam<'w> for QueryFetch<Q, F> {
+    type Item = Query<'w, Q, F>;
+    fn access(access: &mut Access) {
+        let mut types = vec![];
+        Q::type_access(&mut types);
+        F::type_access(&mut types);
+        for (type_id, name, write) in types {
+            access.add_component(type_id, name, write);
+        }
+    }
+    fn fetch(world: &'w World, _: &'w mut (), _: &'static str) -> Self::Item {
+        Query::new(world)
+    }
+}
+
+impl<'w> FetchParam<'w> for CommandsFetch {
+    type Item = Commands<'w>;
+    fn access(_: &mut Access) {}
+    fn fetch(world: &'w World, _: &'w mut (), _: &'static str) -> Self::Item {
+        world.commands()
+    }
+}
+
+impl<'w, T: Default + Send + 'static> FetchParam<'w> for LocalFetch<T> {
+    type Item = Local<'w, T>;
+    fn access(_: &mut Access) {}
+    fn fetch(_: &'w World, value: &'w mut T, _: &'static str) -> Self::Item {
+        Local { value }
+    }
+}
+
+impl<'w, E: Any + Send + Sync> FetchParam<'w> for EventReaderFetch<E> {
+    type Item = EventReader<'w, E>;
+    fn access(access: &mut Access) {
+        ResFetch::<Events<E>>::access(access);
+    }
+    fn fetch(
+        world: &'w World,
+        cursor: &'w mut EventCursor<E>,
+        system: &'static str,
+    ) -> Self::Item {
+        let events = world
+            .resource::<Events<E>>()
+            .unwrap_or_else(|| missing_resource::<Events<E>>(system));
+        EventReader {
+            events: ReadEvents::new(events, cursor),
+        }
+    }
+}
+
+impl<'w, E: Any + Send + Sync> FetchParam<'w> for EventWriterFetch<E> {
+    type Item = EventWriter<'w, E>;
+    fn access(access: &mut Access) {
+        ResMutFetch::<Events<E>>::access(access);
+    }
+    fn fetch(world: &'w World, _: &'w mut (), system: &'static str) -> Self::Item {
+        EventWriter {
+            events: world
+                .resource_mut::<Events<E>>()
+                .unwrap_or_else(|| missing_resource::<Events<E>>(system)),
+        }
+    }
+}
+
+impl<'a, T: Any + Send + Sync> SystemParam for Res<'a, T> {
+    type Fetch = ResFetch<T>;
+}
+
+impl<'a, T: Any + Send + Sync> SystemParam for ResMut<'a, T> {
+    type Fetch = ResMutFetch<T>;
+}
+
+impl<'a, T: Any> SystemParam for NonSend<'a, T> {
+    type Fetch = NonSendFetch<T>;
+}
+
+impl<'a, T: Any> SystemParam for NonSendMut<'a, T> {
+    type Fetch = NonSendMutFetch<T>;
+}
+
+impl<'a, T: Any + Send + Sync> SystemParam for Option<Res<'a, T>> {
+    type Fetch = OptionFetch<ResFetch<T>>;
+}
+
+impl<'a, T: Any + Send + Sync> SystemParam for Option<ResMut<'a, T>> {
+    type Fetch = OptionFetch<ResMutFetch<T>>;
+}
+
+impl<'a, Q, F> SystemParam for Query<'a, Q, F>
+where
+    Q: QueryData<'a>,
+    F: QueryFilter<'a>,
+    QueryFetch<Q, F>: for<'w> FetchParam<'w>,
+{
+    type Fetch = QueryFetch<Q, F>;
+}
+
+impl<'a> SystemParam for Commands<'a> {
+    type Fetch = CommandsFetch;
+}
+
+impl<'a, T: Default + Send + 'static> SystemParam for Local<'a, T> {
+    type Fetch = LocalFetch<T>;
+}
+
+impl<'a, E: Any + Send + Sync> SystemParam for EventReader<'a, E> {
+    type Fetch = EventReaderFetch<E>;
+}
+
+impl<'a, E: Any + Send + Sync> SystemParam for EventWriter<'a, E> {
+    type Fetch = EventWriterFetch<E>;
+}
+
+macro_rules! impl_into_system {
+    ($($param:ident),*) => {
+        #[allow(non_snake_case, unused_variables, unused_mut)]
+        impl<W, Func, $($param: SystemParam),*> IntoSystem<W, (ParamMarker, $($param,)*)> for Func
+        where
+            W: WorldAccess,
+            Func: FnMut($($param),*)
+                + for<'w> FnMut($(<$param::Fetch as FetchParam<'w>>::Item),*)
+                + Send
+                + 'static,
+        {
+            fn into_system(self) -> BoxedSystem<W> {
+                let name = type_name::<Func>();
+                let mut access = Access::new();
+                let mut params: Vec<Access> = vec![];
+                $(
+                    let mut param = Access::new();
+                    <$param::Fetch as FetchParam<'_>>::access(&mut param);
+                    for other in &params {
+                        if let Some(conflict) = param.conflict(other) {
+                            panic!(
+                                "system {} can't run: its parameters lock {} in conflicting ways",
+                                name, conflict
+                            );
+                        }
+                    }
+                    access.extend(&param);
+                    params.push(param);
+                )*
+                let mut func = self;
+                let mut states = ($(<$param::Fetch as ParamState>::State::default(),)*);
+                BoxedSystem {
+                    name,
+                    access,
+                    run: Arc::new(Mutex::new(move |world: &W| {
+                        world.with_world(|world| {
+                            let ($($param,)*) = &mut states;
+                            $(let $param = <$param::Fetch as FetchParam<'_>>::fetch(world, $param, name);)*
+                            func($($param),*);
+                        })
+                    })),
+                }
+            }
+        }
+    };
+}
+
+impl_into_system!();
+impl_into_system!(A);
+impl_into_system!(A, B);
+impl_into_system!(A, B, C);
+impl_into_system!(A, B, C, D);
+impl_into_system!(A, B, C, D, E);
+impl_into_system!(A, B, C, D, E, F);
+impl_into_system!(A, B, C, D, E, F, G);
+impl_into_system!(A, B, C, D, E, F, G, H);
+
+#[test]
+fn typed_parameters() {
+    use crate::work::Work;
+    use crate::world::LockedWorld;
+
+    struct Position(i32);
+    struct Velocity(i32);
+    struct Moved(usize);
+    struct Spawned;
+
+    fn apply_velocity(
+        mut moving: Query<(&mut Position, &Velocity)>,
+        mut moved: ResMut<Moved>,
+        spawned: Option<Res<Spawned>>,
+    ) {
+        assert!(spawned.is_none());
+        for (_, position, velocity) in moving.iter_mut() {
+            position.0 += velocity.0;
+            moved.0 += 1;
+        }
+    }
+
+    fn spawn(mut commands: Commands, moved: Res<Moved>) {
+        let entity = commands.spawn();
+        commands.insert(&entity, Position(moved.0 as i32));
+    }
+
+    let world = LockedWorld::new();
+    let alice = {
+        let mut world = world.lock_exclusive();
+        world.create_resource(Moved(0));
+        let alice = world.create_entity();
+        world.insert(&alice, Position(0));
+        world.insert(&alice, Velocity(2));
+        alice
+    };
+
+    let system: BoxedSystem<LockedWorld> = apply_velocity.into_system();
+    assert!(system.name().ends_with("apply_velocity"));
+    assert!(!system.access().is_exclusive());
+
+    let work = Work::new().add_system(apply_velocity).add_system(spawn);
+    work.run(&world);
+    work.run(&world);
+    world.apply_commands();
+
+    let world = world.lock_shared();
+    assert_eq!(world.resource::<Moved>().unwrap().0, 2);
+    let positions = world.components::<Position>().unwrap();
+    assert_eq!(positions.get(&alice).unwrap().0, 4);
+    assert_eq!(positions.len(), 3);
+}
+
+#[test]
+#[should_panic(expected = "resource retaker::system::missing_resource_panics::Score doesn't exist")]
+fn missing_resource_panics() {
+    struct Score;
+
+    fn keep_score(_: Res<Score>) {}
+
+    let system: BoxedSystem<World> = keep_score.into_system();
+    system.run(&World::new());
+}
+
+#[test]
+#[should_panic(expected = "lock retaker::system::conflicting_parameters::Score in conflicting ways")]
+fn conflicting_parameters() {
+    struct Score;
+
+    fn keep_score(_: Res<Score>, _: ResMut<Score>) {}
+
+    let _: BoxedSystem<World> = keep_score.into_system();
+}
+
+#[test]
+fn shared_reads() {
+    use crate::work::Work;
+
+    struct Health(u32);
+    #[derive(PartialEq)]
+    struct Faction(u32);
+    #[derive(Default)]
+    struct Allies(Vec<u32>);
+
+    fn count_allies(
+        factions: Query<&Faction>,
+        healths: Query<(&Health, &Faction)>,
+        mut allies: ResMut<Allies>,
+    ) {
+        for (id, health, faction) in healths.iter() {
+            let (_, reference) = factions.get(&id).unwrap();
+            if faction == reference {
+                allies.0.push(health.0);
+            }
+        }
+    }
+
+    let mut world = World::new();
+    world.create_resource(Allies::default());
+    for health in 1..=3 {
+        let entity = world.create_entity();
+        world.insert(&entity, Health(health));
+        world.insert(&entity, Faction(0));
+    }
+
+    Work::new().add_system(count_allies).run(&world);
+    assert_eq!(world.resource::<Allies>().unwrap().0, [1, 2, 3]);
+}
+
+#[test]
+fn stateful_systems() {
+    use crate::work::{ThreadedWork, Work};
+
+    #[derive(Default)]
+    struct Runs(Vec<u32>);
+
+    fn count(mut runs: Local<u32>, mut log: ResMut<Runs>) {
+        *runs += 1;
+        log.0.push(*runs);
+    }
+
+    let mut world = World::new();
+    world.create_resource(Runs::default());
+
+    let step = 10;
+    let mut total = 0;
+    let work = Work::new().add_system(count).add_system(move |world: &World| {
+        total += step;
+        world.resource_mut::<Runs>().unwrap().0.push(total);
+    });
+    let threaded = ThreadedWork::new().add_system(count);
+
+    work.run(&world);
+    work.run(&world);
+    threaded.run(&world);
+    threaded.run(&world);
+
+    assert_eq!(world.resource::<Runs>().unwrap().0, [1, 10, 2, 20, 1, 2]);
+}
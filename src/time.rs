@@ -0,0 +1,248 @@
+use std::time::{Duration, Instant};
+
+use crate::work::Run;
+use crate::world::{World, WorldAccess};
+
+/// Time of the current frame, updated by the `Runner` before every tick.
+#[derive(Debug, Clone, Default)]
+pub struct Time {
+    delta: Duration,
+    elapsed: Duration,
+    frame_count: u64,
+}
+
+/// Time of the current fixed step, what systems run by the fixed step
+/// of a `Runner` should read instead of `Time`.
+#[derive(Debug, Clone)]
+pub struct FixedTime {
+    step: Duration,
+    elapsed: Duration,
+    step_count: u64,
+    overstep: Duration,
+}
+
+/// Runs its fixed works every `step` of accumulated time, at most
+/// `max_substeps` times a tick, then its variable works once a tick, then
+/// updates the events of the world.
+pub struct Runner<W> {
+    fixed: Vec<Box<dyn Run<W>>>,
+    variable: Vec<Box<dyn Run<W>>>,
+    step: Duration,
+    max_substeps: u32,
+    accumulator: Duration,
+    last_tick: Option<Instant>,
+}
+
+impl Time {
+    pub fn delta(&self) -> Duration {
+        self.delta
+    }
+    pub fn delta_seconds(&self) -> f32 {
+        self.delta.as_secs_f32()
+    }
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+    pub fn elapsed_seconds(&self) -> f64 {
+        self.elapsed.as_secs_f64()
+    }
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+}
+
+impl Default for FixedTime {
+    /// Steps 60 times a second like a default `Runner`, never zero.
+    fn default() -> Self {
+        FixedTime {
+            step: Duration::from_secs(1) / 60,
+            elapsed: Duration::ZERO,
+            step_count: 0,
+            overstep: Duration::ZERO,
+        }
+    }
+}
+
+impl FixedTime {
+    pub fn delta(&self) -> Duration {
+        self.step
+    }
+    pub fn delta_seconds(&self) -> f32 {
+        self.step.as_secs_f32()
+    }
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+    pub fn step_count(&self) -> u64 {
+        self.step_count
+    }
+    /// How far into the next step the frame is, from 0 to 1, for
+    /// interpolating what the fixed steps simulate.
+    pub fn overstep_fraction(&self) -> f32 {
+        self.overstep.as_secs_f32() / self.step.as_secs_f32()
+    }
+}
+
+impl<W: WorldAccess> Default for Runner<W> {
+    fn default() -> Self {
+        Runner::new()
+    }
+}
+
+impl<W: WorldAccess> Runner<W> {
+    /// A runner stepping 60 times a second, up to 4 steps a tick.
+    pub fn new() -> Runner<W> {
+        Runner {
+            fixed: vec![],
+            variable: vec![],
+            step: Duration::from_secs(1) / 60,
+            max_substeps: 4,
+            accumulator: Duration::ZERO,
+            last_tick: None,
+        }
+    }
+    pub fn fixed_step(mut self, step: Duration) -> Self {
+        assert!(step > Duration::ZERO, "fixed step can't be zero");
+        self.step = step;
+        self
+    }
+    /// Time the fixed works can't keep up with is dropped instead of
+    /// piling up.
+    pub fn max_substeps(mut self, max_substeps: u32) -> Self {
+        assert!(max_substeps > 0, "max substeps can't be zero");
+        self.max_substeps = max_substeps;
+        self
+    }
+    pub fn add_fixed<R: Run<W> + 'static>(mut self, run: R) -> Self {
+        self.fixed.push(Box::new(run));
+        self
+    }
+    pub fn add_variable<R: Run<W> + 'static>(mut self, run: R) -> Self {
+        self.variable.push(Box::new(run));
+        self
+    }
+    /// Ticks with the real time passed since the previous tick.
+    pub fn tick(&mut self, world: &mut W) {
+        let now = Instant::now();
+        let delta = self
+            .last_tick
+            .map_or(Duration::ZERO, |last_tick| now - last_tick);
+        self.last_tick = Some(now);
+        self.tick_with(world, delta);
+    }
+    /// Ticks as if `delta` passed, for deterministic runs.
+    pub fn tick_with(&mut self, world: &mut W, delta: Duration) {
+        world.with_world_mut(|world| {
+            let mut time = world.get_or_insert_with(Time::default);
+            time.delta = delta;
+            time.elapsed += delta;
+            time.frame_count += 1;
+        });
+
+        if !self.fixed.is_empty() {
+            self.accumulator = (self.accumulator + delta).min(self.step * self.max_substeps);
+            while self.accumulator >= self.step {
+                self.accumulator -= self.step;
+                let step = self.step;
+                world.with_world_mut(|world| {
+                    let mut time = world.get_or_insert_with(FixedTime::default);
+                    time.step = step;
+                    time.elapsed += step;
+                    time.step_count += 1;
+                });
+                for run in &mut self.fixed {
+                    run.run(world);
+                }
+            }
+            let overstep = self.accumulator;
+            world.with_world_mut(|world| {
+                world.get_or_insert_with(FixedTime::default).overstep = overstep
+            });
+        }
+
+        for run in &mut self.variable {
+            run.run(world);
+        }
+        world.with_world_mut(World::update_events);
+    }
+}
+
+#[test]
+fn runner_accumulates_fixed_steps() {
+    use crate::work::Work;
+
+    #[derive(Default)]
+    struct Steps(Vec<(u64, u64)>);
+
+    fn physics(world: &World) {
+        let time = world.resource::<FixedTime>().unwrap();
+        let frame = world.resource::<Time>().unwrap().frame_count();
+        world
+            .resource_mut::<Steps>()
+            .unwrap()
+            .0
+            .push((frame, time.step_count()));
+    }
+
+    assert_eq!(FixedTime::default().overstep_fraction(), 0.0);
+
+    let mut world = World::new();
+    world.create_resource(Steps::default());
+    let mut runner = Runner::new()
+        .fixed_step(Duration::from_millis(10))
+        .max_substeps(3)
+        .add_fixed(Work::new().add_system(physics));
+
+    runner.tick_with(&mut world, Duration::from_millis(25));
+    assert_eq!(
+        world.resource::<FixedTime>().unwrap().overstep,
+        Duration::from_millis(5)
+    );
+    runner.tick_with(&mut world, Duration::from_millis(4));
+    runner.tick_with(&mut world, Duration::from_millis(100));
+    runner.tick_with(&mut world, Duration::from_millis(10));
+
+    assert_eq!(
+        world.resource::<Steps>().unwrap().0,
+        [(1, 1), (1, 2), (3, 3), (3, 4), (3, 5), (4, 6)],
+        "fixed steps were not clamped"
+    );
+    let time = world.resource::<Time>().unwrap();
+    assert_eq!(time.elapsed(), Duration::from_millis(139));
+    assert_eq!(time.delta(), Duration::from_millis(10));
+}
+
+#[test]
+#[should_panic(expected = "fixed step can't be zero")]
+fn zero_fixed_step_panics() {
+    Runner::<World>::new().fixed_step(Duration::ZERO);
+}
+
+#[test]
+#[should_panic(expected = "max substeps can't be zero")]
+fn zero_max_substeps_panics() {
+    Runner::<World>::new().max_substeps(0);
+}
+
+#[test]
+fn runner_updates_events_every_tick() {
+    use crate::event::EventCursor;
+
+    struct Damage;
+
+    let mut world = World::new();
+    world.add_event::<Damage>();
+    let mut runner = Runner::new();
+
+    world.send_event(Damage);
+    runner.tick_with(&mut world, Duration::ZERO);
+    let mut reader = EventCursor::<Damage>::new();
+    assert_eq!(world.read_events(&mut reader).iter().count(), 1);
+
+    runner.tick_with(&mut world, Duration::ZERO);
+    let mut late = EventCursor::<Damage>::new();
+    assert!(
+        world.read_events(&mut late).is_empty(),
+        "the runner didn't update events"
+    );
+}
@@ -1,48 +1,616 @@
+use std::any::{type_name, Any, TypeId};
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use hashbrown::HashMap;
+
+use crate::change::SystemTicks;
+use crate::lock_order::{self, Origin};
+use crate::system::{BoxedSystem, IntoSystem};
+use crate::world::WorldAccess;
+
 pub type System<W> = fn(&W) -> ();
 
 pub struct Work<W> {
-    systems: Vec<System<W>>
+    systems: Vec<(BoxedSystem<W>, SystemTicks)>,
+    runner: u64,
 }
 
 pub struct ThreadedWork<W> {
-    systems: Vec<System<W>>
+    systems: Vec<(BoxedSystem<W>, SystemTicks)>,
+    runner: u64,
+}
+
+/// Anything that runs systems over a world, so a `Runner` can drive it.
+pub trait Run<W> {
+    fn run(&mut self, world: &W);
+}
+
+/// Decides whether a system runs. Checked right before the system would
+/// run, a skipped system takes none of its locks.
+pub trait Condition<W>: Send + Sync {
+    fn check(&self, world: &W) -> bool;
+}
+
+pub(crate) type Conditions<W> = Vec<Arc<dyn Condition<W>>>;
+
+pub struct ResourceExists<T>(PhantomData<fn() -> T>);
+
+pub struct ResourceEquals<T>(T);
+
+/// The component lists and resources a system declares it locks. Nothing
+/// checks that a system sticks to it, an undeclared lock is still safe but
+/// may block on a system the `Scheduler` put in the same batch.
+#[derive(Debug, Clone, Default)]
+pub struct Access {
+    components: AccessSet,
+    resources: AccessSet,
+    non_send: AccessSet,
+    exclusive: bool,
+    main_thread: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+struct AccessSet {
+    reads: HashMap<TypeId, &'static str>,
+    writes: HashMap<TypeId, &'static str>,
+}
+
+/// Runs systems in batches of non-conflicting `Access`, each batch on the
+/// rayon pool. A system lands in the first batch after every earlier
+/// system it conflicts with, so conflicting systems keep insertion order.
+/// Systems pinned to the main thread run on the thread calling `run`.
+pub struct Scheduler<W> {
+    systems: Vec<(BoxedSystem<W>, Access, SystemTicks, Conditions<W>)>,
+    batches: Vec<Vec<usize>>,
+    runner: u64,
+    stage: usize,
+}
+
+impl<W> Default for Work<W> {
+    fn default() -> Self {
+        Work::new()
+    }
 }
 
 impl<W> Work<W> {
     pub fn new() -> Work<W> {
         Work {
-            systems: vec![]
+            systems: vec![],
+            runner: lock_order::next_runner(),
         }
     }
-    pub fn add_system(mut self, system: System<W>) -> Self {
-        self.systems.push(system);
+    pub fn add_system<M, S: IntoSystem<W, M>>(mut self, system: S) -> Self {
+        self.systems.push((system.into_system(), SystemTicks::default()));
         self
     }
     pub fn run(&self, world: &W) {
-        for system in &self.systems {
-            system(world)
+        for (i, (system, ticks)) in self.systems.iter().enumerate() {
+            // one system at a time, each is a batch of its own
+            Origin::new(self.runner, (0, i), i).run(|| ticks.run(|| system.run(world)))
         }
     }
 }
 
+impl<W> Run<W> for Work<W> {
+    fn run(&mut self, world: &W) {
+        Work::run(self, world);
+    }
+}
+
+impl<W: Sync> Default for ThreadedWork<W> {
+    fn default() -> Self {
+        ThreadedWork::new()
+    }
+}
 
 impl<W: Sync> ThreadedWork<W> {
     pub fn new() -> ThreadedWork<W> {
         ThreadedWork {
-            systems: vec![]
+            systems: vec![],
+            runner: lock_order::next_runner(),
+        }
+    }
+    pub fn add_system<M, S: IntoSystem<W, M>>(mut self, system: S) -> Self {
+        self.systems.push((system.into_system(), SystemTicks::default()));
+        self
+    }
+    /// Dispatches every system onto the rayon pool at once and returns when
+    /// all of them are done. Systems run in no particular order; the ones
+    /// locking the same `ComponentList` or resource block on its `RwLock`,
+    /// readers overlapping and writers waiting for everyone else. Two
+    /// systems that take several write locks must take them in the same
+    /// order or they can deadlock, debug builds panic as soon as two locks
+    /// are taken in both orders. Systems pinned to the main thread run on
+    /// the calling thread.
+    pub fn run(&self, world: &W) {
+        let runner = self.runner;
+        rayon::in_place_scope(|scope| {
+            for (i, (system, ticks)) in self.systems.iter().enumerate() {
+                let origin = Origin::new(runner, (0, 0), i);
+                if !system.access().is_main_thread() {
+                    scope.spawn(move |_| origin.run(|| ticks.run(|| system.run(world))));
+                }
+            }
+            for (i, (system, ticks)) in self.systems.iter().enumerate() {
+                let origin = Origin::new(runner, (0, 0), i);
+                if system.access().is_main_thread() {
+                    origin.run(|| ticks.run(|| system.run(world)));
+                }
+            }
+        });
+    }
+}
+
+impl<W: Sync> Run<W> for ThreadedWork<W> {
+    fn run(&mut self, world: &W) {
+        ThreadedWork::run(self, world);
+    }
+}
+
+impl<W, F: Fn(&W) -> bool + Send + Sync + 'static> Condition<W> for F {
+    fn check(&self, world: &W) -> bool {
+        self(world)
+    }
+}
+
+impl<W: WorldAccess, T: Any + Send + Sync> Condition<W> for ResourceExists<T> {
+    fn check(&self, world: &W) -> bool {
+        world.with_world(|world| world.resource::<T>().is_some())
+    }
+}
+
+impl<W: WorldAccess, T: Any + Send + Sync + PartialEq> Condition<W> for ResourceEquals<T> {
+    fn check(&self, world: &W) -> bool {
+        world.with_world(|world| world.resource::<T>().is_some_and(|resource| *resource == self.0))
+    }
+}
+
+pub fn resource_exists<T: Any + Send + Sync>() -> ResourceExists<T> {
+    ResourceExists(PhantomData)
+}
+
+/// Runs the system while the resource `T` exists and equals `value`.
+pub fn resource_equals<T: Any + Send + Sync + PartialEq>(value: T) -> ResourceEquals<T> {
+    ResourceEquals(value)
+}
+
+impl AccessSet {
+    fn insert(&mut self, type_id: TypeId, name: &'static str, write: bool) {
+        if write {
+            self.writes.insert(type_id, name);
+        } else {
+            self.reads.insert(type_id, name);
+        }
+    }
+    /// The name of a type one of the sets writes and the other one locks.
+    fn conflict(&self, other: &AccessSet) -> Option<&'static str> {
+        self.writes
+            .iter()
+            .find(|(t, _)| other.reads.contains_key(*t) || other.writes.contains_key(*t))
+            .or_else(|| other.writes.iter().find(|(t, _)| self.reads.contains_key(*t)))
+            .map(|(_, name)| *name)
+    }
+    fn extend(&mut self, other: &AccessSet) {
+        self.reads.extend(other.reads.iter().map(|(t, name)| (*t, *name)));
+        self.writes.extend(other.writes.iter().map(|(t, name)| (*t, *name)));
+    }
+}
+
+impl Access {
+    pub fn new() -> Access {
+        Access::default()
+    }
+    /// Access to the whole world, e.g. a system calling `lock_exclusive`.
+    pub fn exclusive() -> Access {
+        Access {
+            exclusive: true,
+            ..Access::default()
+        }
+    }
+    pub fn read<T: Any>(mut self) -> Self {
+        self.components.reads.insert(TypeId::of::<T>(), type_name::<T>());
+        self
+    }
+    pub fn write<T: Any>(mut self) -> Self {
+        self.components.writes.insert(TypeId::of::<T>(), type_name::<T>());
+        self
+    }
+    pub fn read_resource<T: Any>(mut self) -> Self {
+        self.resources.reads.insert(TypeId::of::<T>(), type_name::<T>());
+        self
+    }
+    pub fn write_resource<T: Any>(mut self) -> Self {
+        self.resources.writes.insert(TypeId::of::<T>(), type_name::<T>());
+        self
+    }
+    /// Pins the system to the thread running the `Scheduler`, which must be
+    /// the one that created the world.
+    pub fn read_non_send<T: Any>(mut self) -> Self {
+        self.add_non_send(TypeId::of::<T>(), type_name::<T>(), false);
+        self
+    }
+    pub fn write_non_send<T: Any>(mut self) -> Self {
+        self.add_non_send(TypeId::of::<T>(), type_name::<T>(), true);
+        self
+    }
+    /// Pins the system to the thread running the `Scheduler`, e.g. for a
+    /// system calling into a windowing library.
+    pub fn main_thread(mut self) -> Self {
+        self.main_thread = true;
+        self
+    }
+    pub(crate) fn add_component(&mut self, type_id: TypeId, name: &'static str, write: bool) {
+        self.components.insert(type_id, name, write);
+    }
+    pub(crate) fn add_resource(&mut self, type_id: TypeId, name: &'static str, write: bool) {
+        self.resources.insert(type_id, name, write);
+    }
+    pub(crate) fn add_non_send(&mut self, type_id: TypeId, name: &'static str, write: bool) {
+        self.non_send.insert(type_id, name, write);
+        self.main_thread = true;
+    }
+    /// Adds the locks and main thread pin `system` derives from its
+    /// parameters, which a declared access can't leave out. Exclusivity
+    /// isn't added, a plain `fn(&W)` is only exclusive until it declares
+    /// what it locks.
+    pub(crate) fn merge_derived(&mut self, system: &Access) {
+        let exclusive = self.exclusive;
+        self.extend(system);
+        self.exclusive = exclusive;
+    }
+    pub(crate) fn extend(&mut self, other: &Access) {
+        self.components.extend(&other.components);
+        self.resources.extend(&other.resources);
+        self.non_send.extend(&other.non_send);
+        self.exclusive |= other.exclusive;
+        self.main_thread |= other.main_thread;
+    }
+    pub fn is_exclusive(&self) -> bool {
+        self.exclusive
+    }
+    pub fn is_main_thread(&self) -> bool {
+        self.main_thread
+    }
+    /// The component list or resource both accesses lock, one of them for
+    /// writing.
+    pub(crate) fn conflict(&self, other: &Access) -> Option<&'static str> {
+        self.components
+            .conflict(&other.components)
+            .or_else(|| self.resources.conflict(&other.resources))
+            .or_else(|| self.non_send.conflict(&other.non_send))
+    }
+    pub fn conflicts_with(&self, other: &Access) -> bool {
+        self.exclusive || other.exclusive || self.conflict(other).is_some()
+    }
+}
+
+impl<W: Sync> Default for Scheduler<W> {
+    fn default() -> Self {
+        Scheduler::new()
+    }
+}
+
+impl<W: Sync> Scheduler<W> {
+    pub fn new() -> Scheduler<W> {
+        Scheduler::with_runner(lock_order::next_runner(), 0)
+    }
+    /// A scheduler running as stage `stage` of `runner`, so the lock
+    /// orders of its batches are told apart from the other stages.
+    pub(crate) fn with_runner(runner: u64, stage: usize) -> Scheduler<W> {
+        Scheduler {
+            systems: vec![],
+            batches: vec![],
+            runner,
+            stage,
         }
     }
-    pub fn add_system(mut self, system: System<W>) -> Self {
-        self.systems.push(system);
+    /// The locks the system derives from its parameters, and its main
+    /// thread pin, are added to `access`.
+    pub fn add_system<M, S: IntoSystem<W, M>>(mut self, system: S, access: Access) -> Self {
+        self.push(system.into_system(), access, &[], vec![]);
+        self
+    }
+    pub fn add_system_if<M, S: IntoSystem<W, M>, C: Condition<W> + 'static>(
+        mut self,
+        system: S,
+        access: Access,
+        condition: C,
+    ) -> Self {
+        self.push(system.into_system(), access, &[], vec![Arc::new(condition)]);
         self
     }
+    /// Also keeps the system in a later batch than the systems in `after`.
+    pub(crate) fn push(
+        &mut self,
+        system: BoxedSystem<W>,
+        access: Access,
+        after: &[usize],
+        conditions: Conditions<W>,
+    ) -> usize {
+        let mut access = access;
+        access.merge_derived(system.access());
+        let index = self.systems.len();
+        let mut batch = 0;
+        for (i, other) in self.batches.iter().enumerate().rev() {
+            if other.iter().any(|&other| {
+                after.contains(&other) || self.systems[other].1.conflicts_with(&access)
+            }) {
+                batch = i + 1;
+                break;
+            }
+        }
+        if batch == self.batches.len() {
+            self.batches.push(vec![]);
+        }
+        self.batches[batch].push(index);
+        self.systems
+            .push((system, access, SystemTicks::default(), conditions));
+        index
+    }
+    fn run_system(&self, batch: usize, index: usize, world: &W) {
+        let (system, _, ticks, conditions) = &self.systems[index];
+        Origin::new(self.runner, (self.stage, batch), index).run(|| {
+            if conditions.iter().all(|condition| condition.check(world)) {
+                ticks.run(|| system.run(world));
+            } else {
+                ticks.skip();
+            }
+        });
+    }
     pub fn run(&self, world: &W) {
-        for system in &self.systems {
-            rayon::scope(|scope| {
-                scope.spawn(|_| {
-                    system(world)
-                });
+        for (i, batch) in self.batches.iter().enumerate() {
+            if let [single] = batch[..] {
+                self.run_system(i, single, world);
+                continue;
+            }
+            rayon::in_place_scope(|scope| {
+                let (pinned, spawned) = batch
+                    .iter()
+                    .partition::<Vec<usize>, _>(|&&index| self.systems[index].1.is_main_thread());
+                for index in spawned {
+                    scope.spawn(move |_| self.run_system(i, index, world));
+                }
+                for index in pinned {
+                    self.run_system(i, index, world);
+                }
             });
         }
     }
-}
\ No newline at end of file
+}
+
+impl<W: Sync> Run<W> for Scheduler<W> {
+    fn run(&mut self, world: &W) {
+        Scheduler::run(self, world);
+    }
+}
+
+#[test]
+fn threaded_work_overlaps_systems() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::{Duration, Instant};
+
+    struct Rendezvous {
+        arrived: AtomicUsize,
+        met: AtomicUsize,
+    }
+
+    fn meet(world: &Rendezvous) {
+        world.arrived.fetch_add(1, Ordering::SeqCst);
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(5) {
+            if world.arrived.load(Ordering::SeqCst) == 2 {
+                world.met.fetch_add(1, Ordering::SeqCst);
+                return;
+            }
+        }
+    }
+
+    let world = Rendezvous {
+        arrived: AtomicUsize::new(0),
+        met: AtomicUsize::new(0),
+    };
+    let work = ThreadedWork::new().add_system(meet).add_system(meet);
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(2)
+        .build()
+        .unwrap();
+    pool.install(|| work.run(&world));
+
+    assert_eq!(
+        world.met.load(Ordering::SeqCst),
+        2,
+        "threaded systems did not run concurrently"
+    );
+}
+
+#[test]
+fn scheduler_batches_non_conflicting_systems() {
+    struct A;
+    struct B;
+    struct Res;
+
+    fn noop(_: &()) {}
+
+    let scheduler = Scheduler::<()>::new()
+        .add_system(noop, Access::new().write::<A>())
+        .add_system(noop, Access::new().read::<B>().read_resource::<Res>())
+        .add_system(noop, Access::new().read::<A>())
+        .add_system(noop, Access::new().write::<B>())
+        .add_system(noop, Access::new().read::<A>().read_resource::<Res>())
+        .add_system(noop, Access::exclusive())
+        .add_system(noop, Access::new().write_resource::<Res>());
+
+    assert_eq!(
+        scheduler.batches,
+        vec![vec![0, 1], vec![2, 3, 4], vec![5], vec![6]],
+        "incorrectly batched systems"
+    );
+
+    scheduler.run(&());
+}
+
+#[test]
+fn systems_see_changes_since_their_last_run() {
+    use crate::query::{Added, Changed};
+    use crate::world::LockedWorld;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct Health(i32);
+
+    static ADDED: AtomicUsize = AtomicUsize::new(0);
+    static CHANGED: AtomicUsize = AtomicUsize::new(0);
+    static REMOVED: AtomicUsize = AtomicUsize::new(0);
+
+    fn detect(world: &LockedWorld) {
+        let world = world.lock_shared();
+        let added = world.query_filtered::<&Health, Added<Health>>().len();
+        let changed = world.query_filtered::<&Health, Changed<Health>>().len();
+        ADDED.store(added, Ordering::SeqCst);
+        CHANGED.store(changed, Ordering::SeqCst);
+        REMOVED.store(world.removed::<Health>().len(), Ordering::SeqCst);
+    }
+
+    let world = LockedWorld::new();
+    let work = Work::new().add_system(detect);
+
+    let (alice, bob) = {
+        let mut world = world.lock_exclusive();
+        let alice = world.create_entity();
+        let bob = world.create_entity();
+        world.insert(&alice, Health(3));
+        world.insert(&bob, Health(3));
+        (alice, bob)
+    };
+
+    work.run(&world);
+    assert_eq!(ADDED.load(Ordering::SeqCst), 2);
+    assert_eq!(CHANGED.load(Ordering::SeqCst), 2);
+
+    work.run(&world);
+    assert_eq!(ADDED.load(Ordering::SeqCst), 0, "additions were reported twice");
+    assert_eq!(CHANGED.load(Ordering::SeqCst), 0, "changes were reported twice");
+
+    {
+        let world = world.lock_shared();
+        world.components_mut::<Health>().unwrap().get_mut(&alice).unwrap().0 -= 1;
+        world.remove::<Health>(&bob);
+    }
+
+    work.run(&world);
+    assert_eq!(ADDED.load(Ordering::SeqCst), 0);
+    assert_eq!(CHANGED.load(Ordering::SeqCst), 1);
+    assert_eq!(REMOVED.load(Ordering::SeqCst), 1);
+
+    work.run(&world);
+    assert_eq!(CHANGED.load(Ordering::SeqCst), 0);
+    assert_eq!(REMOVED.load(Ordering::SeqCst), 0);
+}
+
+#[test]
+fn systems_do_not_see_their_own_changes() {
+    use crate::query::{Changed, Query};
+    use crate::system::ResMut;
+    use crate::world::World;
+
+    struct Health(i32);
+    struct Healed(Vec<usize>);
+
+    fn heal(mut healths: Query<&mut Health, Changed<Health>>, mut healed: ResMut<Healed>) {
+        let mut count = 0;
+        for (_, health) in healths.iter_mut() {
+            health.0 += 1;
+            count += 1;
+        }
+        healed.0.push(count);
+    }
+
+    let mut world = World::new();
+    world.create_resource(Healed(vec![]));
+    let entity = world.create_entity();
+    world.insert(&entity, Health(1));
+
+    let work = Work::new().add_system(heal);
+    work.run(&world);
+    work.run(&world);
+    assert_eq!(world.resource::<Healed>().unwrap().0, vec![1, 0]);
+
+    world.components_mut::<Health>().unwrap().get_mut(&entity).unwrap().0 = 0;
+    work.run(&world);
+    work.run(&world);
+    assert_eq!(world.resource::<Healed>().unwrap().0, vec![1, 0, 1, 0]);
+}
+
+#[test]
+fn removal_logs_stay_bounded() {
+    use crate::world::World;
+
+    struct Health;
+    struct Removed(Vec<usize>);
+
+    fn remove_one(world: &World) {
+        let entity = world.query::<&Health>().entities()[0].clone();
+        world.remove::<Health>(&entity);
+    }
+    fn count_removed(world: &World) {
+        world.resource_mut::<Removed>().unwrap().0.push(world.removed::<Health>().len());
+    }
+
+    let mut world = World::new();
+    world.create_resource(Removed(vec![]));
+    for _ in 0..100 {
+        let entity = world.create_entity();
+        world.insert(&entity, Health);
+    }
+
+    let work = Work::new().add_system(remove_one).add_system(count_removed);
+    for _ in 0..100 {
+        work.run(&world);
+    }
+    assert_eq!(world.resource::<Removed>().unwrap().0, vec![1; 100]);
+    // outside of a system every removal still logged is reported
+    assert!(world.removed::<Health>().len() <= 2, "removal log kept growing");
+}
+
+#[test]
+fn idle_systems_do_not_hold_back_removals() {
+    use crate::world::World;
+
+    struct Health;
+    #[derive(PartialEq)]
+    struct Counting(bool);
+
+    fn remove_one(world: &World) {
+        let entity = world.query::<&Health>().entities()[0].clone();
+        world.remove::<Health>(&entity);
+    }
+    fn count_removed(world: &World) {
+        world.removed::<Health>();
+    }
+
+    let mut world = World::new();
+    world.create_resource(Counting(true));
+    for _ in 0..100 {
+        let entity = world.create_entity();
+        world.insert(&entity, Health);
+    }
+
+    // reads the removals of another world only
+    let idle = Work::new().add_system(count_removed);
+    idle.run(&World::new());
+    let skipped = Scheduler::new().add_system_if(
+        count_removed,
+        Access::new(),
+        resource_equals(Counting(true)),
+    );
+    skipped.run(&world);
+    world.resource_mut::<Counting>().unwrap().0 = false;
+
+    let work = Work::new().add_system(remove_one).add_system(count_removed);
+    for _ in 0..100 {
+        work.run(&world);
+        skipped.run(&world);
+    }
+    assert!(world.removed::<Health>().len() <= 2, "removal log kept growing");
+}
@@ -1,17 +1,48 @@
-use std::any::{Any, TypeId};
+use std::any::{type_name, Any, TypeId};
+use std::fmt;
 use std::hash::Hash;
 use std::ops::{Deref, DerefMut};
 use std::vec::IntoIter;
 
 use hashbrown::HashMap;
+use crate::bundle::Bundle;
+use crate::change::{Tick, TickTable, WorldTicks};
+use crate::commands::{Command, Commands};
+use crate::event::{self, EventCursor, Events, ReadEvents};
+use crate::hierarchy::{self, Children, Parent};
+use crate::hooks::{self, ComponentHooks};
+use crate::lock_order::{Held, LockId, LockOrder};
+use crate::non_send::{NonSendResourceMut, NonSendResourceRef, NonSendResources};
+use crate::query::{Query, QueryData, QueryFilter};
+use crate::storage::{ComponentStorage, StorageType};
+
 use parking_lot::{Mutex, RwLock, RwLockReadGuard, RwLockUpgradableReadGuard, RwLockWriteGuard};
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub struct EntityId(u64);
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EntityId {
+    index: u32,
+    generation: u32,
+}
+
+impl EntityId {
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+    pub fn to_bits(&self) -> u64 {
+        (self.generation as u64) << 32 | self.index as u64
+    }
+}
 
 impl From<u64> for EntityId {
     fn from(val: u64) -> Self {
-        EntityId(val)
+        EntityId {
+            index: val as u32,
+            generation: (val >> 32) as u32,
+        }
     }
 }
 
@@ -28,18 +59,104 @@ pub struct LockedWorld {
     world: RwLock<World>,
 }
 
+/// A resource type along with its label, `None` for the unlabeled one.
+type ResourceKey = (TypeId, Option<&'static str>);
+
 pub struct World {
     component_table: HashMap<TypeId, Box<dyn TypeErasedListTrait>>,
-    resource_table: HashMap<TypeId, Box<dyn Any>>,
-    next_entity_id: Mutex<u64>,
+    resource_table: HashMap<ResourceId, (ResourceKey, Box<dyn Any + Send + Sync>)>,
+    resource_ids: HashMap<ResourceKey, ResourceId>,
+    non_send: NonSendResources,
+    entities: Mutex<Entities>,
     next_resource_id: Mutex<u64>,
+    command_queue: Mutex<Vec<Command>>,
+    event_updaters: Vec<fn(&World)>,
+    hooks: HashMap<TypeId, ComponentHooks>,
+    ticks: WorldTicks,
+    lock_order: LockOrder,
+}
+
+struct EntitySlot {
+    generation: u32,
+    alive: bool,
 }
 
-unsafe impl Sync for World {}
-unsafe impl Send for World {}
+/// Hands out entity ids, recycling the index of deleted entities with a
+/// bumped generation so stale ids never alias a new entity.
+#[derive(Default)]
+struct Entities {
+    slots: Vec<EntitySlot>,
+    free: Vec<u32>,
+}
+
+impl Entities {
+    fn alloc(&mut self) -> EntityId {
+        if let Some(index) = self.free.pop() {
+            let slot = &mut self.slots[index as usize];
+            slot.alive = true;
+            EntityId {
+                index,
+                generation: slot.generation,
+            }
+        } else {
+            let index = self.slots.len() as u32;
+            self.slots.push(EntitySlot {
+                generation: 0,
+                alive: true,
+            });
+            EntityId {
+                index,
+                generation: 0,
+            }
+        }
+    }
+    /// Allocates `count` fresh, contiguous indices, leaving the free ones
+    /// to single allocations.
+    fn alloc_batch(&mut self, count: usize) -> Vec<EntityId> {
+        let start = self.slots.len() as u32;
+        self.slots.extend((0..count).map(|_| EntitySlot {
+            generation: 0,
+            alive: true,
+        }));
+        (start..start + count as u32)
+            .map(|index| EntityId {
+                index,
+                generation: 0,
+            })
+            .collect()
+    }
+    fn free(&mut self, entity: &EntityId) -> bool {
+        if !self.is_alive(entity) {
+            return false;
+        }
+        let slot = &mut self.slots[entity.index as usize];
+        slot.alive = false;
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free.push(entity.index);
+        true
+    }
+    fn is_alive(&self, entity: &EntityId) -> bool {
+        match self.slots.get(entity.index as usize) {
+            Some(slot) => slot.alive && slot.generation == entity.generation,
+            None => false,
+        }
+    }
+    fn alive(&self) -> Vec<EntityId> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter(|(_, slot)| slot.alive)
+            .map(|(index, slot)| EntityId {
+                index: index as u32,
+                generation: slot.generation,
+            })
+            .collect()
+    }
+}
 
 pub struct ComponentList<T: Any + Send + Sync> {
-    components: RwLock<HashMap<EntityId, T>>,
+    components: RwLock<ComponentStorage<T>>,
+    ticks: TickTable,
 }
 
 pub struct Resource<T: Any + Send + Sync> {
@@ -48,20 +165,28 @@ pub struct Resource<T: Any + Send + Sync> {
 
 #[derive(Debug)]
 pub struct ComponentListRef<'a, T: Any + Send + Sync> {
-    pub(crate) lock: RwLockReadGuard<'a, HashMap<EntityId, T>>,
+    pub(crate) lock: RwLockReadGuard<'a, ComponentStorage<T>>,
+    _held: Held,
 }
 
 #[derive(Debug)]
 pub struct ComponentListMut<'a, T: Any + Send + Sync> {
-    pub(crate) lock: RwLockWriteGuard<'a, HashMap<EntityId, T>>,
+    pub(crate) lock: RwLockWriteGuard<'a, ComponentStorage<T>>,
+    ticks: &'a TickTable,
+    world_ticks: &'a WorldTicks,
+    /// Stamped on every write through the guard.
+    now: Tick,
+    _held: Held,
 }
 
 pub struct ResourceRef<'a, T: Any + Send + Sync> {
     lock: RwLockReadGuard<'a, T>,
+    _held: Held,
 }
 
 pub struct ResourceMut<'a, T: Any + Send + Sync> {
     lock: RwLockWriteGuard<'a, T>,
+    _held: Held,
 }
 
 #[derive(Clone)]
@@ -69,22 +194,108 @@ pub struct QueriedEntities {
     entities: Vec<EntityId>,
 }
 
-trait TypeErasedListTrait {
+/// Why a `try_` accessor of the `World` failed, naming the Rust type
+/// involved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WorldError {
+    /// No component of the type was ever inserted.
+    MissingComponent(&'static str),
+    MissingResource(&'static str),
+    DeadEntity(EntityId),
+    /// A `try_lock_` accessor found the component list or resource locked
+    /// in a conflicting way.
+    Locked(&'static str),
+}
+
+impl fmt::Display for WorldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WorldError::MissingComponent(component) => {
+                write!(f, "component {} was never inserted", component)
+            }
+            WorldError::MissingResource(resource) => {
+                write!(f, "resource {} doesn't exist", resource)
+            }
+            WorldError::DeadEntity(entity) => write!(f, "entity {:?} is dead", entity),
+            WorldError::Locked(name) => write!(f, "{} is locked elsewhere", name),
+        }
+    }
+}
+
+impl std::error::Error for WorldError {}
+
+trait TypeErasedListTrait: Send + Sync {
     fn as_any(&self) -> &dyn Any;
-    fn remove(&self, entity: &EntityId);
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+    fn storage_type(&self) -> StorageType;
+    fn contains(&self, entity: &EntityId) -> bool;
+    fn remove_batch(&mut self, entities: &[EntityId], now: Tick, seen: Option<Tick>);
+    fn clear_removed(&mut self);
+}
+
+impl<T: Any + Send + Sync> ComponentList<T> {
+    fn new(storage: StorageType) -> ComponentList<T> {
+        ComponentList {
+            components: RwLock::new(ComponentStorage::new(storage)),
+            ticks: TickTable::default(),
+        }
+    }
 }
 
 impl<T: Any + Send + Sync> TypeErasedListTrait for ComponentList<T> {
     fn as_any(&self) -> &dyn Any {
         self
     }
-    fn remove(&self, entity: &EntityId) {
-        let upgradeable = self.components.upgradable_read();
-        if upgradeable.contains_key(entity) {
-            let mut write = RwLockUpgradableReadGuard::upgrade(upgradeable);
-            write.remove(&entity);
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+    fn storage_type(&self) -> StorageType {
+        self.components.read_recursive().storage_type()
+    }
+    fn contains(&self, entity: &EntityId) -> bool {
+        self.components.read_recursive().contains(entity)
+    }
+    fn remove_batch(&mut self, entities: &[EntityId], now: Tick, seen: Option<Tick>) {
+        let components = self.components.get_mut();
+        for entity in entities {
+            if components.remove(entity).is_some() {
+                self.ticks.mark_removed(entity, now, seen);
+            }
         }
     }
+    fn clear_removed(&mut self) {
+        self.ticks.clear_removed();
+    }
+}
+
+/// What systems run on, lets code generic over it read the `World`.
+pub trait WorldAccess {
+    fn with_world<R>(&self, f: impl FnOnce(&World) -> R) -> R;
+    fn with_world_mut<R>(&mut self, f: impl FnOnce(&mut World) -> R) -> R;
+}
+
+impl WorldAccess for World {
+    fn with_world<R>(&self, f: impl FnOnce(&World) -> R) -> R {
+        f(self)
+    }
+    fn with_world_mut<R>(&mut self, f: impl FnOnce(&mut World) -> R) -> R {
+        f(self)
+    }
+}
+
+impl WorldAccess for LockedWorld {
+    fn with_world<R>(&self, f: impl FnOnce(&World) -> R) -> R {
+        f(&self.lock_shared())
+    }
+    fn with_world_mut<R>(&mut self, f: impl FnOnce(&mut World) -> R) -> R {
+        f(self.world.get_mut())
+    }
+}
+
+impl Default for LockedWorld {
+    fn default() -> Self {
+        LockedWorld::new()
+    }
 }
 
 impl LockedWorld {
@@ -93,21 +304,27 @@ impl LockedWorld {
             world: RwLock::new(World::new()),
         }
     }
-    pub fn lock_shared(&self) -> ReaderWorldGuard {
+    pub fn lock_shared(&self) -> ReaderWorldGuard<'_> {
         ReaderWorldGuard {
             lock: self.world.read(),
         }
     }
-    pub fn lock_exclusive(&self) -> WriterWorldGuard {
+    pub fn lock_exclusive(&self) -> WriterWorldGuard<'_> {
         WriterWorldGuard {
             lock: self.world.write(),
         }
     }
-    pub fn lock_upgradable(&self) -> UpgradableReaderWorldGuard {
+    pub fn lock_upgradable(&self) -> UpgradableReaderWorldGuard<'_> {
         UpgradableReaderWorldGuard {
             lock: RwLockWriteGuard::downgrade_to_upgradable(self.world.write()),
         }
     }
+    pub fn apply_commands(&self) {
+        self.lock_exclusive().apply_commands();
+    }
+    pub fn update_events(&self) {
+        self.lock_exclusive().update_events();
+    }
 }
 
 pub struct WriterWorldGuard<'a> {
@@ -157,110 +374,664 @@ impl<'a> UpgradableReaderWorldGuard<'a> {
     }
 }
 
+impl Default for World {
+    fn default() -> Self {
+        World::new()
+    }
+}
+
 impl World {
     pub fn new() -> World {
         World {
             component_table: HashMap::new(),
             resource_table: HashMap::new(),
-            next_entity_id: Mutex::new(0),
+            resource_ids: HashMap::new(),
+            non_send: NonSendResources::new(),
+            entities: Mutex::new(Entities::default()),
             next_resource_id: Mutex::new(0),
+            command_queue: Mutex::new(vec![]),
+            event_updaters: vec![],
+            hooks: HashMap::new(),
+            ticks: WorldTicks::default(),
+            lock_order: LockOrder::new(),
         }
     }
-    pub fn components<'a, T: Any + Send + Sync>(
-        &'a self,
-    ) -> Option<ComponentListRef<'a, T>> {
-        if let Some(list) = self.component_table.get(&TypeId::of::<T>()) {
-            let list = list.as_any().downcast_ref::<ComponentList<T>>().unwrap();
-            Some(ComponentListRef {
-                lock: list.components.read(),
-            })
-        } else {
-            None
-        }
+    pub fn components<T: Any + Send + Sync>(&self) -> Option<ComponentListRef<'_, T>> {
+        let list = self.component_list::<T>().ok()?;
+        let held = self.lock_order.acquire(
+            LockId::Component(TypeId::of::<T>()),
+            type_name::<T>(),
+            false,
+        );
+        Some(ComponentListRef {
+            lock: list.components.read_recursive(),
+            _held: held,
+        })
     }
-    pub fn components_mut<'a, T: Any + Send + Sync>(
-        &'a self,
-    ) -> Option<ComponentListMut<'a, T>> {
-        if let Some(list) = self.component_table.get(&TypeId::of::<T>()) {
-            let list = list.as_any().downcast_ref::<ComponentList<T>>().unwrap();
-            Some(ComponentListMut {
-                lock: list.components.write(),
-            })
-        } else {
-            None
-        }
+    pub fn components_mut<T: Any + Send + Sync>(&self) -> Option<ComponentListMut<'_, T>> {
+        let list = self.component_list::<T>().ok()?;
+        let held = self.lock_order.acquire(
+            LockId::Component(TypeId::of::<T>()),
+            type_name::<T>(),
+            true,
+        );
+        Some(ComponentListMut {
+            lock: list.components.write(),
+            ticks: &list.ticks,
+            world_ticks: &self.ticks,
+            now: self.ticks.now(),
+            _held: held,
+        })
     }
-    pub fn resource<'a, T: Any + Send + Sync>(&'a self) -> Option<ResourceRef<'a, T>> {
-        if let Some(resource) = self.resource_table.get(&TypeId::of::<T>()) {
-            let lock = resource.downcast_ref::<Resource<T>>().unwrap();
-            Some(ResourceRef {
-                lock: lock.resource.read(),
-            })
-        } else {
-            None
-        }
+    pub fn resource<T: Any + Send + Sync>(&self) -> Option<ResourceRef<'_, T>> {
+        self.labeled_resource::<T>(None)
     }
-    pub fn resource_mut<'a, T: Any + Send + Sync>(&'a self) -> Option<ResourceMut<'a, T>> {
-        if let Some(resource) = self.resource_table.get(&TypeId::of::<T>()) {
-            let lock = resource.downcast_ref::<Resource<T>>().unwrap();
-            Some(ResourceMut {
-                lock: lock.resource.write(),
-            })
-        } else {
-            None
-        }
+    pub fn resource_mut<T: Any + Send + Sync>(&self) -> Option<ResourceMut<'_, T>> {
+        self.labeled_resource_mut::<T>(None)
+    }
+    /// The `T` created with `label`, or the unlabeled one for `None`.
+    pub fn labeled_resource<T: Any + Send + Sync>(
+        &self,
+        label: Option<&'static str>,
+    ) -> Option<ResourceRef<'_, T>> {
+        self.resource_by_id(&self.labeled_resource_id::<T>(label)?)
+    }
+    pub fn labeled_resource_mut<T: Any + Send + Sync>(
+        &self,
+        label: Option<&'static str>,
+    ) -> Option<ResourceMut<'_, T>> {
+        self.resource_mut_by_id(&self.labeled_resource_id::<T>(label)?)
+    }
+    /// `None` if there's no such resource or it isn't a `T`.
+    pub fn resource_by_id<T: Any + Send + Sync>(
+        &self,
+        id: &ResourceId,
+    ) -> Option<ResourceRef<'_, T>> {
+        let lock = self.resource_lock_by_id::<T>(id)?;
+        let held = self
+            .lock_order
+            .acquire(LockId::Resource(id.clone()), type_name::<T>(), false);
+        Some(ResourceRef {
+            lock: lock.read_recursive(),
+            _held: held,
+        })
+    }
+    pub fn resource_mut_by_id<T: Any + Send + Sync>(
+        &self,
+        id: &ResourceId,
+    ) -> Option<ResourceMut<'_, T>> {
+        let lock = self.resource_lock_by_id::<T>(id)?;
+        let held = self
+            .lock_order
+            .acquire(LockId::Resource(id.clone()), type_name::<T>(), true);
+        Some(ResourceMut {
+            lock: lock.write(),
+            _held: held,
+        })
+    }
+    pub fn resource_id<T: Any + Send + Sync>(&self) -> Option<ResourceId> {
+        self.labeled_resource_id::<T>(None)
+    }
+    pub fn labeled_resource_id<T: Any + Send + Sync>(
+        &self,
+        label: Option<&'static str>,
+    ) -> Option<ResourceId> {
+        self.resource_ids.get(&(TypeId::of::<T>(), label)).cloned()
+    }
+    /// Like `components`, but saying why it failed.
+    pub fn try_components<T: Any + Send + Sync>(
+        &self,
+    ) -> Result<ComponentListRef<'_, T>, WorldError> {
+        self.components::<T>()
+            .ok_or(WorldError::MissingComponent(type_name::<T>()))
+    }
+    pub fn try_components_mut<T: Any + Send + Sync>(
+        &self,
+    ) -> Result<ComponentListMut<'_, T>, WorldError> {
+        self.components_mut::<T>()
+            .ok_or(WorldError::MissingComponent(type_name::<T>()))
+    }
+    pub fn try_resource<T: Any + Send + Sync>(&self) -> Result<ResourceRef<'_, T>, WorldError> {
+        self.resource::<T>()
+            .ok_or(WorldError::MissingResource(type_name::<T>()))
+    }
+    pub fn try_resource_mut<T: Any + Send + Sync>(&self) -> Result<ResourceMut<'_, T>, WorldError> {
+        self.resource_mut::<T>()
+            .ok_or(WorldError::MissingResource(type_name::<T>()))
+    }
+    /// Like `try_components`, but failing instead of blocking when the list
+    /// is locked mutably.
+    pub fn try_lock_components<T: Any + Send + Sync>(
+        &self,
+    ) -> Result<ComponentListRef<'_, T>, WorldError> {
+        let list = self.component_list::<T>()?;
+        let lock = list
+            .components
+            .try_read_recursive()
+            .ok_or(WorldError::Locked(type_name::<T>()))?;
+        let held = self.lock_order.acquired(
+            LockId::Component(TypeId::of::<T>()),
+            type_name::<T>(),
+            false,
+        );
+        Ok(ComponentListRef { lock, _held: held })
+    }
+    /// Like `try_components_mut`, but failing instead of blocking when the
+    /// list is locked.
+    pub fn try_lock_components_mut<T: Any + Send + Sync>(
+        &self,
+    ) -> Result<ComponentListMut<'_, T>, WorldError> {
+        let list = self.component_list::<T>()?;
+        let lock = list
+            .components
+            .try_write()
+            .ok_or(WorldError::Locked(type_name::<T>()))?;
+        let held = self.lock_order.acquired(
+            LockId::Component(TypeId::of::<T>()),
+            type_name::<T>(),
+            true,
+        );
+        Ok(ComponentListMut {
+            lock,
+            ticks: &list.ticks,
+            world_ticks: &self.ticks,
+            now: self.ticks.now(),
+            _held: held,
+        })
+    }
+    /// Like `try_resource`, but failing instead of blocking when the
+    /// resource is locked mutably.
+    pub fn try_lock_resource<T: Any + Send + Sync>(
+        &self,
+    ) -> Result<ResourceRef<'_, T>, WorldError> {
+        let (id, lock) = self.resource_lock::<T>()?;
+        let lock = lock
+            .try_read_recursive()
+            .ok_or(WorldError::Locked(type_name::<T>()))?;
+        let held = self
+            .lock_order
+            .acquired(LockId::Resource(id), type_name::<T>(), false);
+        Ok(ResourceRef { lock, _held: held })
+    }
+    /// Like `try_resource_mut`, but failing instead of blocking when the
+    /// resource is locked.
+    pub fn try_lock_resource_mut<T: Any + Send + Sync>(
+        &self,
+    ) -> Result<ResourceMut<'_, T>, WorldError> {
+        let (id, lock) = self.resource_lock::<T>()?;
+        let lock = lock
+            .try_write()
+            .ok_or(WorldError::Locked(type_name::<T>()))?;
+        let held = self
+            .lock_order
+            .acquired(LockId::Resource(id), type_name::<T>(), true);
+        Ok(ResourceMut { lock, _held: held })
+    }
+    fn component_list<T: Any + Send + Sync>(&self) -> Result<&ComponentList<T>, WorldError> {
+        self.component_table
+            .get(&TypeId::of::<T>())
+            .map(|list| list.as_any().downcast_ref::<ComponentList<T>>().unwrap())
+            .ok_or(WorldError::MissingComponent(type_name::<T>()))
+    }
+    fn resource_lock<T: Any + Send + Sync>(&self) -> Result<(ResourceId, &RwLock<T>), WorldError> {
+        self.resource_id::<T>()
+            .and_then(|id| Some((id.clone(), self.resource_lock_by_id::<T>(&id)?)))
+            .ok_or(WorldError::MissingResource(type_name::<T>()))
+    }
+    fn resource_lock_by_id<T: Any + Send + Sync>(&self, id: &ResourceId) -> Option<&RwLock<T>> {
+        let (_, resource) = self.resource_table.get(id)?;
+        Some(&resource.downcast_ref::<Resource<T>>()?.resource)
     }
     pub fn delete_resource<T: Any + Send + Sync>(&mut self) {
-        self.resource_table.remove(&TypeId::of::<T>());
+        if let Some(id) = self.resource_id::<T>() {
+            self.delete_resource_by_id(&id);
+        }
     }
+    /// Returns whether there was a resource with that id.
+    pub fn delete_resource_by_id(&mut self, id: &ResourceId) -> bool {
+        match self.resource_table.remove(id) {
+            Some((key, _)) => {
+                self.resource_ids.remove(&key);
+                true
+            }
+            None => false,
+        }
+    }
+    /// Panics if there already is a `T`, use `insert_resource` to replace
+    /// it.
     pub fn create_resource<T: Any + Send + Sync>(&mut self, resource: T) -> ResourceId {
-        let mut id_guard = self.next_resource_id.lock();
-        let id = id_guard.clone();
+        self.create_labeled_resource(None, resource)
+    }
+    /// Creates a `T` told apart from other `T`s by its `label`, `None` being
+    /// the one `resource` and systems see.
+    pub fn create_labeled_resource<T: Any + Send + Sync>(
+        &mut self,
+        label: Option<&'static str>,
+        resource: T,
+    ) -> ResourceId {
+        let key = (TypeId::of::<T>(), label);
+        assert!(
+            !self.resource_ids.contains_key(&key),
+            "resource {} labeled {:?} already exists",
+            type_name::<T>(),
+            label
+        );
+        let id_guard = self.next_resource_id.get_mut();
+        let id = ResourceId(*id_guard);
         *id_guard += 1;
         self.resource_table.insert(
-            TypeId::of::<T>(),
-            Box::new(Resource {
-                resource: RwLock::new(resource),
-            }),
+            id.clone(),
+            (
+                key,
+                Box::new(Resource {
+                    resource: RwLock::new(resource),
+                }),
+            ),
         );
-        id.into()
+        self.resource_ids.insert(key, id.clone());
+        id
+    }
+    pub fn contains_resource<T: Any + Send + Sync>(&self) -> bool {
+        self.resource_id::<T>().is_some()
+    }
+    /// Removes the `T`, handing it back instead of dropping it.
+    pub fn take_resource<T: Any + Send + Sync>(&mut self) -> Option<T> {
+        let id = self.resource_id::<T>()?;
+        self.resource_ids.remove(&(TypeId::of::<T>(), None));
+        let (_, resource) = self.resource_table.remove(&id)?;
+        Some(resource.downcast::<Resource<T>>().ok()?.resource.into_inner())
+    }
+    /// Swaps in a new value for the existing `T` through its lock, so it
+    /// works from systems too, returning the previous value.
+    pub fn replace_resource<T: Any + Send + Sync>(&self, resource: T) -> Result<T, WorldError> {
+        let mut previous = self
+            .resource_mut::<T>()
+            .ok_or(WorldError::MissingResource(type_name::<T>()))?;
+        Ok(std::mem::replace(&mut *previous, resource))
+    }
+    /// Creates a default `T` unless there already is one.
+    pub fn init_resource<T: Any + Send + Sync + Default>(&mut self) -> ResourceId {
+        match self.resource_id::<T>() {
+            Some(id) => id,
+            None => self.create_resource(T::default()),
+        }
+    }
+    /// The `T`, created with `f` first if there's none.
+    pub fn get_or_insert_with<T: Any + Send + Sync>(
+        &mut self,
+        f: impl FnOnce() -> T,
+    ) -> ResourceMut<'_, T> {
+        if !self.contains_resource::<T>() {
+            self.create_resource(f());
+        }
+        self.resource_mut::<T>().unwrap()
+    }
+    /// Stores a resource that isn't `Send` or `Sync`, returning the previous
+    /// one. Like every non-send accessor, panics when not called from the
+    /// thread that created the world.
+    pub fn insert_non_send_resource<T: Any>(&mut self, resource: T) -> Option<T> {
+        self.non_send.insert(resource)
+    }
+    pub fn non_send_resource<T: Any>(&self) -> Option<NonSendResourceRef<'_, T>> {
+        self.non_send.get::<T>()
+    }
+    pub fn non_send_resource_mut<T: Any>(&self) -> Option<NonSendResourceMut<'_, T>> {
+        self.non_send.get_mut::<T>()
+    }
+    pub fn take_non_send_resource<T: Any>(&mut self) -> Option<T> {
+        self.non_send.remove::<T>()
+    }
+    pub fn contains_non_send_resource<T: Any>(&self) -> bool {
+        self.non_send.contains::<T>()
+    }
+    /// Whether this is the thread that created the world, the only one
+    /// non-send resources can be reached from.
+    pub fn is_main_thread(&self) -> bool {
+        self.non_send.is_owner()
+    }
+    /// Creates the `T` or replaces it, keeping its id, returning the
+    /// previous value.
+    pub fn insert_resource<T: Any + Send + Sync>(&mut self, resource: T) -> Option<T> {
+        match self.resource_id::<T>() {
+            Some(id) => {
+                let (_, previous) = self.resource_table.get_mut(&id).unwrap();
+                let previous = previous.downcast_mut::<Resource<T>>().unwrap();
+                Some(std::mem::replace(previous.resource.get_mut(), resource))
+            }
+            None => {
+                self.create_resource(resource);
+                None
+            }
+        }
     }
     pub fn delete_entity(&mut self, entity: &EntityId) {
-        for list in self.component_table.iter() {
-            list.1.remove(entity);
+        self.despawn_batch(std::slice::from_ref(entity));
+    }
+    /// Deletes every entity of `entities` that is alive, going over each
+    /// component list once.
+    pub fn despawn_batch(&mut self, entities: &[EntityId]) {
+        let mut despawned = Vec::with_capacity(entities.len());
+        for entity in entities {
+            if !self.entities.get_mut().is_alive(entity) {
+                continue;
+            }
+            hierarchy::detach(self, entity);
+            let hooked = self
+                .component_table
+                .iter()
+                .filter(|(type_id, list)| {
+                    self.hooks.contains_key(*type_id) && list.contains(entity)
+                })
+                .map(|(type_id, _)| *type_id)
+                .collect::<Vec<_>>();
+            for type_id in hooked {
+                hooks::run(&self.hooks[&type_id].on_remove, self, entity);
+            }
+            self.entities.get_mut().free(entity);
+            despawned.push(entity.clone());
+        }
+        if despawned.is_empty() {
+            return;
+        }
+        let now = self.ticks.now();
+        let seen = self.ticks.removals_seen_by_all();
+        for list in self.component_table.values_mut() {
+            list.remove_batch(&despawned, now, seen);
         }
     }
     pub fn create_entity(&self) -> EntityId {
-        let mut id_guard = self.next_entity_id.lock();
-        let id = id_guard.clone();
-        *id_guard += 1;
-        id.into()
+        self.entities.lock().alloc()
+    }
+    pub fn is_alive(&self, entity: &EntityId) -> bool {
+        self.entities.lock().is_alive(entity)
+    }
+    /// Makes `child` a child of `parent`, taking it from its previous parent.
+    pub fn set_parent(&mut self, child: &EntityId, parent: &EntityId) {
+        hierarchy::set_parent(self, child, parent);
+    }
+    /// Makes `child` a root again, returning its previous parent.
+    pub fn remove_parent(&mut self, child: &EntityId) -> Option<EntityId> {
+        hierarchy::remove_parent(self, child)
+    }
+    pub fn parent(&self, entity: &EntityId) -> Option<EntityId> {
+        self.components::<Parent>()?
+            .get(entity)
+            .map(|parent| parent.get().clone())
+    }
+    pub fn children(&self, entity: &EntityId) -> Vec<EntityId> {
+        self.components::<Children>()
+            .and_then(|list| list.get(entity).map(|children| children.iter().cloned().collect()))
+            .unwrap_or_default()
+    }
+    pub fn descendants(&self, entity: &EntityId) -> Vec<EntityId> {
+        hierarchy::descendants(self, entity)
+    }
+    /// Deletes `entity` along with all of its descendants.
+    pub fn despawn_recursive(&mut self, entity: &EntityId) {
+        for descendant in self.descendants(entity) {
+            self.delete_entity(&descendant);
+        }
+        self.delete_entity(entity);
+    }
+    pub fn commands(&self) -> Commands<'_> {
+        Commands::new(self)
+    }
+    pub(crate) fn queue_commands(&self, commands: &mut Vec<Command>) {
+        self.command_queue.lock().append(commands);
+    }
+    /// Runs every queued command, including the ones queued while applying.
+    pub fn apply_commands(&mut self) {
+        loop {
+            let queue = std::mem::take(self.command_queue.get_mut());
+            if queue.is_empty() {
+                break;
+            }
+            for command in queue {
+                command(self);
+            }
+        }
+    }
+    /// Creates the `Events<E>` resource and has `update_events` update it.
+    pub fn add_event<E: Any + Send + Sync>(&mut self) {
+        if self.resource::<Events<E>>().is_none() {
+            self.create_resource(Events::<E>::new());
+            self.event_updaters.push(event::update_events::<E>);
+        }
+    }
+    pub fn send_event<E: Any + Send + Sync>(&self, event: E) {
+        self.resource_mut::<Events<E>>()
+            .unwrap_or_else(|| panic!("event {} was not added", std::any::type_name::<E>()))
+            .send(event);
+    }
+    pub fn read_events<'a, E: Any + Send + Sync>(
+        &'a self,
+        cursor: &mut EventCursor<E>,
+    ) -> ReadEvents<'a, E> {
+        let events = self
+            .resource::<Events<E>>()
+            .unwrap_or_else(|| panic!("event {} was not added", std::any::type_name::<E>()));
+        ReadEvents::new(events, cursor)
+    }
+    /// Swaps the buffers of every added event type, dropping the events
+    /// sent before the previous update. A `Runner` calls it after every
+    /// tick, without one call it between runs.
+    pub fn update_events(&mut self) {
+        for update in &self.event_updaters {
+            update(self);
+        }
+    }
+    pub(crate) fn alive_entities(&self) -> Vec<EntityId> {
+        self.entities.lock().alive()
+    }
+    pub fn query<'w, Q: QueryData<'w>>(&'w self) -> Query<'w, Q> {
+        Query::new(self)
+    }
+    pub fn query_filtered<'w, Q: QueryData<'w>, F: QueryFilter<'w>>(&'w self) -> Query<'w, Q, F> {
+        Query::new(self)
     }
     pub fn insert<T: Any + Send + Sync>(&mut self, entity: &EntityId, component: T) -> Option<T> {
-        if let Some(mut list) = self.components_mut() {
-            return list.lock.insert(entity.clone(), component);
+        assert!(
+            self.entities.get_mut().is_alive(entity),
+            "tried to insert a component into dead entity {:?}",
+            entity
+        );
+        let list = self
+            .component_table
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(ComponentList::<T>::new(StorageType::default_for::<T>())))
+            .as_any_mut()
+            .downcast_mut::<ComponentList<T>>()
+            .unwrap();
+        let previous = list.components.get_mut().insert(entity.clone(), component);
+        list.ticks.insert(entity, previous.is_none(), self.ticks.now());
+        if let Some(hooks) = self.hooks.get(&TypeId::of::<T>()) {
+            if previous.is_none() {
+                hooks::run(&hooks.on_add, self, entity);
+            }
+            hooks::run(&hooks.on_insert, self, entity);
         }
-
-        let mut components = HashMap::new();
-
-        components.insert(entity.clone(), component);
-
-        self.component_table.insert(
-            TypeId::of::<T>(),
-            Box::new(ComponentList::<T> {
-                components: RwLock::new(components),
-            }),
+        previous
+    }
+    /// Inserts `components[i]` into `entities[i]`, growing the storage of
+    /// `T` once for all of them.
+    pub fn insert_batch<T: Any + Send + Sync>(
+        &mut self,
+        entities: &[EntityId],
+        components: Vec<T>,
+    ) {
+        assert_eq!(
+            entities.len(),
+            components.len(),
+            "tried to insert {} components into {} entities",
+            components.len(),
+            entities.len()
         );
-
-        None
+        let alive = self.entities.get_mut();
+        if let Some(dead) = entities.iter().find(|entity| !alive.is_alive(entity)) {
+            panic!("tried to insert a component into dead entity {:?}", dead);
+        }
+        let list = self
+            .component_table
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(ComponentList::<T>::new(StorageType::default_for::<T>())))
+            .as_any_mut()
+            .downcast_mut::<ComponentList<T>>()
+            .unwrap();
+        let end = entities
+            .iter()
+            .map(|entity| entity.index() as usize + 1)
+            .max()
+            .unwrap_or(0);
+        let storage = list.components.get_mut();
+        storage.reserve(entities.len(), end);
+        list.ticks.reserve(end);
+        let now = self.ticks.now();
+        let mut added = Vec::with_capacity(entities.len());
+        for (entity, component) in entities.iter().zip(components) {
+            let is_new = storage.insert(entity.clone(), component).is_none();
+            list.ticks.insert(entity, is_new, now);
+            added.push(is_new);
+        }
+        if let Some(hooks) = self.hooks.get(&TypeId::of::<T>()) {
+            for (entity, is_new) in entities.iter().zip(added) {
+                if is_new {
+                    hooks::run(&hooks.on_add, self, entity);
+                }
+                hooks::run(&hooks.on_insert, self, entity);
+            }
+        }
+    }
+    /// Like `insert`, but failing instead of panicking when `entity` is dead.
+    pub fn try_insert<T: Any + Send + Sync>(
+        &mut self,
+        entity: &EntityId,
+        component: T,
+    ) -> Result<Option<T>, WorldError> {
+        if !self.entities.get_mut().is_alive(entity) {
+            return Err(WorldError::DeadEntity(entity.clone()));
+        }
+        Ok(self.insert(entity, component))
+    }
+    /// Creates an entity with every component of `bundle`.
+    pub fn spawn<B: Bundle>(&mut self, bundle: B) -> EntityId {
+        let entity = self.create_entity();
+        bundle.insert_into(self, &entity);
+        entity
+    }
+    /// Creates an entity for every bundle, from a contiguous range of new
+    /// ids, inserting the bundles a component type at a time.
+    pub fn spawn_batch<B: Bundle, I: IntoIterator<Item = B>>(
+        &mut self,
+        bundles: I,
+    ) -> Vec<EntityId> {
+        let bundles = bundles.into_iter().collect::<Vec<_>>();
+        let entities = self.entities.get_mut().alloc_batch(bundles.len());
+        B::insert_batch(self, &entities, bundles);
+        entities
+    }
+    pub fn insert_bundle<B: Bundle>(&mut self, entity: &EntityId, bundle: B) {
+        assert!(
+            self.entities.get_mut().is_alive(entity),
+            "tried to insert a bundle into dead entity {:?}",
+            entity
+        );
+        bundle.insert_into(self, entity);
+    }
+    /// Removes the components of `B` if `entity` has all of them.
+    pub fn remove_bundle<B: Bundle>(&self, entity: &EntityId) -> Option<B> {
+        B::remove_from(self, entity)
+    }
+    /// Picks how the components of `T` are stored, moving over the ones
+    /// already inserted.
+    pub fn register_storage<T: Any + Send + Sync>(&mut self, storage: StorageType) {
+        self.component_table
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(ComponentList::<T>::new(storage)))
+            .as_any_mut()
+            .downcast_mut::<ComponentList<T>>()
+            .unwrap()
+            .components
+            .get_mut()
+            .convert(storage);
+    }
+    pub fn storage_type<T: Any + Send + Sync>(&self) -> Option<StorageType> {
+        self.component_table
+            .get(&TypeId::of::<T>())
+            .map(|list| list.storage_type())
     }
     pub fn remove<T: Any + Send + Sync>(&self, entity: &EntityId) -> Option<T> {
+        self.run_remove_hooks::<T>(entity);
         if let Some(mut list) = self.components_mut::<T>() {
-            list.lock.remove(entity)
+            list.remove(entity)
         } else {
             None
         }
     }
+    /// Runs the `on_remove` hooks of `T` if `entity` has one.
+    pub(crate) fn run_remove_hooks<T: Any + Send + Sync>(&self, entity: &EntityId) {
+        if let Some(hooks) = self.hooks.get(&TypeId::of::<T>()) {
+            if self.components::<T>().is_some_and(|list| list.contains(entity)) {
+                hooks::run(&hooks.on_remove, self, entity);
+            }
+        }
+    }
+    /// Runs `hook` whenever an entity that didn't have a `T` gets one.
+    pub fn on_add<T: Any + Send + Sync, F: Fn(&World, &EntityId) + Send + Sync + 'static>(
+        &mut self,
+        hook: F,
+    ) {
+        self.hooks
+            .entry(TypeId::of::<T>())
+            .or_default()
+            .on_add
+            .push(Box::new(hook));
+    }
+    /// Runs `hook` every time a `T` is inserted, replacing one or not.
+    pub fn on_insert<T: Any + Send + Sync, F: Fn(&World, &EntityId) + Send + Sync + 'static>(
+        &mut self,
+        hook: F,
+    ) {
+        self.hooks
+            .entry(TypeId::of::<T>())
+            .or_default()
+            .on_insert
+            .push(Box::new(hook));
+    }
+    /// Runs `hook` right before a `T` is removed, also when its entity is
+    /// deleted. Removing through a locked `ComponentListMut` skips it.
+    pub fn on_remove<T: Any + Send + Sync, F: Fn(&World, &EntityId) + Send + Sync + 'static>(
+        &mut self,
+        hook: F,
+    ) {
+        self.hooks
+            .entry(TypeId::of::<T>())
+            .or_default()
+            .on_remove
+            .push(Box::new(hook));
+    }
+    /// When the system running on this thread last ran on this world.
+    pub(crate) fn last_run(&self) -> Tick {
+        self.ticks.last_run()
+    }
+    pub(crate) fn component_ticks<T: Any + Send + Sync>(&self) -> Option<&TickTable> {
+        self.component_table
+            .get(&TypeId::of::<T>())
+            .map(|list| &list.as_any().downcast_ref::<ComponentList<T>>().unwrap().ticks)
+    }
+    /// Entities that lost their `T` since the calling system last ran.
+    pub fn removed<T: Any + Send + Sync>(&self) -> Vec<EntityId> {
+        self.component_ticks::<T>()
+            .map_or(vec![], |ticks| ticks.removed_since(self.ticks.removals_seen()))
+    }
+    /// Forgets the removals `removed` reports. The ones every system reading
+    /// them saw are already forgotten, this also drops the ones systems are
+    /// still waiting for, and the ones nothing but code outside of systems
+    /// reads, which are kept until then.
+    pub fn clear_trackers(&mut self) {
+        for list in self.component_table.values_mut() {
+            list.clear_removed();
+        }
+    }
 }
 
 impl IntoIterator for QueriedEntities {
@@ -274,28 +1045,32 @@ impl IntoIterator for QueriedEntities {
 impl<'a, T: Any + Send + Sync> ComponentListRef<'a, T> {
     pub fn query(&self) -> QueriedEntities {
         QueriedEntities {
-            entities: self.lock.keys().cloned().collect(),
+            entities: self.lock.entities(),
         }
     }
     pub fn with(&self, mut query: QueriedEntities) -> QueriedEntities {
-        query.entities = query
-            .entities
-            .into_iter()
-            .filter(|e| self.lock.contains_key(e))
-            .collect();
+        query.entities.retain(|e| self.lock.contains(e));
         query
     }
     pub fn without(&self, mut query: QueriedEntities) -> QueriedEntities {
-        query.entities = query
-            .entities
-            .into_iter()
-            .filter(|e| !self.lock.contains_key(e))
-            .collect();
+        query.entities.retain(|e| !self.lock.contains(e));
         query
     }
     pub fn get(&self, entity: &EntityId) -> Option<&T> {
         self.lock.get(entity)
     }
+    pub fn contains(&self, entity: &EntityId) -> bool {
+        self.lock.contains(entity)
+    }
+    pub fn len(&self) -> usize {
+        self.lock.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.lock.len() == 0
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (&EntityId, &T)> {
+        self.lock.iter()
+    }
 }
 
 impl<'a, T: Any + Send + Sync> Deref for ResourceRef<'a, T> {
@@ -321,42 +1096,80 @@ impl<'a, T: Any + Send + Sync> DerefMut for ResourceMut<'a, T> {
 impl<'a, T: Any + Send + Sync> ComponentListMut<'a, T> {
     pub fn query(&self) -> QueriedEntities {
         QueriedEntities {
-            entities: self.lock.keys().cloned().collect(),
+            entities: self.lock.entities(),
         }
     }
     pub fn with(&self, mut query: QueriedEntities) -> QueriedEntities {
-        query.entities = query
-            .entities
-            .into_iter()
-            .filter(|e| self.lock.contains_key(e))
-            .collect();
+        query.entities.retain(|e| self.lock.contains(e));
         query
     }
     pub fn without(&self, mut query: QueriedEntities) -> QueriedEntities {
-        query.entities = query
-            .entities
-            .into_iter()
-            .filter(|e| !self.lock.contains_key(e))
-            .collect();
+        query.entities.retain(|e| !self.lock.contains(e));
         query
     }
     pub fn get(&self, entity: &EntityId) -> Option<&T> {
         self.lock.get(entity)
     }
     pub fn get_mut(&mut self, entity: &EntityId) -> Option<&mut T> {
-        self.lock.get_mut(entity)
+        let component = self.lock.get_mut(entity)?;
+        self.ticks.mark_changed(entity, self.now);
+        Some(component)
+    }
+    pub(crate) fn get_mut_ptr(&mut self, entity: &EntityId) -> Option<*mut T> {
+        let component = self.lock.get_mut_ptr(entity)?;
+        self.ticks.mark_changed(entity, self.now);
+        Some(component)
+    }
+    pub fn contains(&self, entity: &EntityId) -> bool {
+        self.lock.contains(entity)
+    }
+    pub fn len(&self) -> usize {
+        self.lock.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.lock.len() == 0
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (&EntityId, &T)> {
+        self.lock.iter()
+    }
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&EntityId, &mut T)> {
+        let (ticks, now) = (self.ticks, self.now);
+        self.lock.iter_mut().map(move |(entity, component)| {
+            ticks.mark_changed(entity, now);
+            (entity, component)
+        })
     }
     pub fn get_many_mut<const N: usize>(
         &mut self,
         entities: [&EntityId; N],
     ) -> Option<[&mut T; N]> {
-        self.lock.get_many_mut(entities)
+        let components = self.lock.get_many_mut(entities)?;
+        for entity in entities {
+            self.ticks.mark_changed(entity, self.now);
+        }
+        Some(components)
     }
     pub fn clear(&mut self) {
+        let seen = self.world_ticks.removals_seen_by_all();
+        for entity in self.lock.entities() {
+            self.ticks.mark_removed(&entity, self.now, seen);
+        }
         self.lock.clear();
     }
     pub fn remove(&mut self, entity: &EntityId) -> Option<T> {
-        self.lock.remove(entity)
+        let component = self.lock.remove(entity)?;
+        let seen = self.world_ticks.removals_seen_by_all();
+        self.ticks.mark_removed(entity, self.now, seen);
+        Some(component)
+    }
+    /// Removes the components `keep` returns false for, kept ones aren't
+    /// marked changed. Like `remove` and `clear`, it runs no `on_remove`
+    /// hooks and leaves the hierarchy alone, `World::remove` does both.
+    pub fn retain(&mut self, keep: impl FnMut(&EntityId, &T) -> bool) {
+        let seen = self.world_ticks.removals_seen_by_all();
+        for entity in self.lock.retain(keep) {
+            self.ticks.mark_removed(&entity, self.now, seen);
+        }
     }
 }
 
@@ -485,3 +1298,381 @@ fn query_resource() {
         }
     }
 }
+
+#[test]
+fn generational_entity_ids() {
+    let mut world = World::new();
+
+    let alice = world.create_entity();
+    world.insert(&alice, 1u32);
+    world.delete_entity(&alice);
+
+    assert!(!world.is_alive(&alice), "deleted entity is still alive");
+    assert!(world.remove::<u32>(&alice).is_none());
+
+    let bob = world.create_entity();
+    assert_eq!(bob.index(), alice.index(), "entity index was not recycled");
+    assert_ne!(bob, alice, "recycled entity aliases the deleted one");
+    assert!(world.is_alive(&bob));
+
+    world.insert(&bob, 2u32);
+    world.delete_entity(&alice);
+    assert_eq!(world.components::<u32>().unwrap().get(&bob), Some(&2));
+}
+
+#[test]
+#[should_panic]
+fn insert_into_dead_entity() {
+    let mut world = World::new();
+
+    let alice = world.create_entity();
+    world.delete_entity(&alice);
+    world.insert(&alice, 1u32);
+}
+
+#[test]
+fn register_storage_keeps_components() {
+    struct Dead;
+    struct Tile(u32);
+
+    let mut world = World::new();
+
+    let alice = world.create_entity();
+    let bob = world.create_entity();
+    world.insert(&alice, Dead);
+    world.insert(&alice, Tile(1));
+    world.insert(&bob, Tile(2));
+
+    assert_eq!(world.storage_type::<Dead>(), Some(StorageType::Tag));
+    assert_eq!(world.storage_type::<Tile>(), Some(StorageType::SparseSet));
+
+    world.register_storage::<Tile>(StorageType::DenseVec);
+    assert_eq!(world.storage_type::<Tile>(), Some(StorageType::DenseVec));
+
+    let tiles = world.components::<Tile>().unwrap();
+    let dead = world.components::<Dead>().unwrap();
+    assert_eq!(tiles.get(&bob).map(|t| t.0), Some(2));
+    for dead_id in tiles.with(dead.query()) {
+        assert!(dead_id == alice, "incorrectly queried tag storage");
+    }
+}
+
+#[test]
+fn clear_trackers_bounds_removal_logs() {
+    struct Tile;
+
+    let mut world = World::new();
+    for _ in 0..100 {
+        let entity = world.create_entity();
+        world.insert(&entity, Tile);
+        world.remove::<Tile>(&entity);
+        // without systems reading them, removals are kept until cleared
+        assert_eq!(world.removed::<Tile>(), [entity]);
+        world.clear_trackers();
+    }
+    assert!(world.removed::<Tile>().is_empty());
+}
+
+#[test]
+fn batch_spawn_and_despawn() {
+    #[derive(Debug, PartialEq)]
+    struct Tile(u32);
+    struct Solid;
+
+    let mut world = World::new();
+    let freed = world.create_entity();
+    world.delete_entity(&freed);
+
+    let tiles = world.spawn_batch((0..100).map(|i| (Tile(i), Solid)));
+    assert_eq!(tiles.len(), 100);
+    assert!(
+        tiles
+            .windows(2)
+            .all(|pair| pair[1].index() == pair[0].index() + 1),
+        "batch ids are not contiguous"
+    );
+    assert_eq!(
+        world.components::<Tile>().unwrap().get(&tiles[42]),
+        Some(&Tile(42))
+    );
+    assert_eq!(world.create_entity().index(), freed.index());
+
+    world
+        .components_mut::<Tile>()
+        .unwrap()
+        .retain(|_, tile| tile.0 % 2 == 0);
+    assert_eq!(world.components::<Tile>().unwrap().len(), 50);
+    assert_eq!(world.components::<Solid>().unwrap().len(), 100);
+
+    world.despawn_batch(&tiles[..10]);
+    assert!(!world.is_alive(&tiles[0]));
+    assert_eq!(world.components::<Tile>().unwrap().len(), 45);
+    assert_eq!(world.components::<Solid>().unwrap().len(), 90);
+}
+
+#[test]
+fn fallible_accessors() {
+    #[derive(Debug)]
+    struct Faction;
+    struct Score(u32);
+
+    let mut world = World::new();
+    let entity = world.create_entity();
+    assert_eq!(
+        world.try_components::<Faction>().unwrap_err().to_string(),
+        format!("component {} was never inserted", type_name::<Faction>())
+    );
+    assert!(matches!(
+        world.try_resource::<Score>(),
+        Err(WorldError::MissingResource(_))
+    ));
+
+    world.create_resource(Score(0));
+    let score = world.try_resource_mut::<Score>().unwrap();
+    assert!(matches!(
+        world.try_lock_resource::<Score>(),
+        Err(WorldError::Locked(_))
+    ));
+    drop(score);
+    world.try_lock_resource_mut::<Score>().unwrap().0 += 1;
+    assert_eq!(world.try_resource::<Score>().unwrap().0, 1);
+
+    world.delete_entity(&entity);
+    assert_eq!(
+        world.try_insert(&entity, Faction).unwrap_err(),
+        WorldError::DeadEntity(entity)
+    );
+}
+
+#[test]
+fn resources_by_id_and_label() {
+    #[derive(Debug, PartialEq)]
+    struct Camera(&'static str);
+
+    let mut world = World::new();
+    let main = world.create_resource(Camera("main"));
+    let minimap = world.create_labeled_resource(Some("minimap"), Camera("minimap"));
+    assert_ne!(main, minimap);
+    assert_eq!(world.resource::<Camera>().unwrap().0, "main");
+    assert_eq!(
+        world.labeled_resource::<Camera>(Some("minimap")).unwrap().0,
+        "minimap"
+    );
+    assert!(world.resource_by_id::<u32>(&minimap).is_none());
+
+    assert_eq!(
+        world.insert_resource(Camera("player")),
+        Some(Camera("main"))
+    );
+    assert_eq!(world.resource_id::<Camera>(), Some(main.clone()));
+    assert_eq!(world.resource_by_id::<Camera>(&main).unwrap().0, "player");
+
+    assert!(world.delete_resource_by_id(&minimap));
+    assert!(!world.delete_resource_by_id(&minimap));
+    assert!(world.labeled_resource::<Camera>(Some("minimap")).is_none());
+    assert!(world.resource::<Camera>().is_some());
+}
+
+#[test]
+fn resources_move_between_worlds() {
+    #[derive(Debug, Default, PartialEq)]
+    struct Level(u32);
+
+    let mut first = World::new();
+    let mut second = World::new();
+    first.init_resource::<Level>();
+    first.resource_mut::<Level>().unwrap().0 = 1;
+    let id = first.init_resource::<Level>();
+    assert_eq!(first.resource_by_id::<Level>(&id).unwrap().0, 1);
+
+    let level = first.take_resource::<Level>().unwrap();
+    assert!(!first.contains_resource::<Level>());
+    assert!(first.resource_by_id::<Level>(&id).is_none());
+    second.insert_resource(level);
+
+    assert_eq!(second.replace_resource(Level(2)), Ok(Level(1)));
+    assert_eq!(
+        first.replace_resource(Level(3)),
+        Err(WorldError::MissingResource(type_name::<Level>()))
+    );
+    second.get_or_insert_with(|| Level(4)).0 += 1;
+    first.get_or_insert_with(|| Level(4)).0 += 1;
+    assert_eq!(*second.resource::<Level>().unwrap(), Level(3));
+    assert_eq!(*first.resource::<Level>().unwrap(), Level(5));
+}
+
+#[test]
+fn world_is_send_and_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<World>();
+    assert_send_sync::<LockedWorld>();
+}
+
+#[test]
+fn concurrent_component_and_resource_access() {
+    struct Position(u32);
+    struct Velocity(u32);
+    struct Steps(u32);
+
+    // kept small under miri, which checks these tests for data races with
+    // `MIRIFLAGS=-Zmiri-permissive-provenance cargo +nightly miri test --lib concurrent`,
+    // the flag silencing parking_lot's integer to pointer casts. They use
+    // std threads, rayon's pool trips miri inside crossbeam-epoch.
+    let (tasks, rounds) = if cfg!(miri) { (4, 4) } else { (64, 200) };
+
+    let mut world = World::new();
+    let entities = world.spawn_batch((0..16).map(|i| (Position(0), Velocity(i))));
+    world.create_resource(Steps(0));
+
+    std::thread::scope(|scope| {
+        for _ in 0..tasks {
+            scope.spawn(|| {
+                for _ in 0..rounds {
+                    let velocities = world.components::<Velocity>().unwrap();
+                    let mut positions = world.components_mut::<Position>().unwrap();
+                    for (entity, velocity) in velocities.iter() {
+                        positions.get_mut(entity).unwrap().0 += velocity.0;
+                    }
+                    drop((velocities, positions));
+                    world.resource_mut::<Steps>().unwrap().0 += 1;
+                }
+            });
+        }
+    });
+
+    assert_eq!(world.resource::<Steps>().unwrap().0, tasks * rounds);
+    let positions = world.components::<Position>().unwrap();
+    for (i, entity) in entities.iter().enumerate() {
+        assert_eq!(
+            positions.get(entity).unwrap().0,
+            i as u32 * tasks * rounds,
+            "lost an update"
+        );
+    }
+}
+
+#[test]
+fn concurrent_inserts_and_removals_against_queries() {
+    struct Position(u32);
+    struct Velocity(u32);
+
+    let (tasks, rounds) = if cfg!(miri) { (2, 4) } else { (8, 200) };
+
+    let world = LockedWorld::new();
+
+    std::thread::scope(|scope| {
+        for _ in 0..tasks {
+            scope.spawn(|| {
+                for i in 0..rounds {
+                    let entity = {
+                        let mut world = world.lock_exclusive();
+                        let entity = world.create_entity();
+                        world.insert(&entity, Position(i));
+                        world.insert(&entity, Velocity(i));
+                        entity
+                    };
+                    // removing only needs the world shared, racing the queries
+                    let world = world.lock_shared();
+                    assert!(world.remove::<Velocity>(&entity).is_some());
+                    assert!(world.remove::<Position>(&entity).is_some());
+                }
+            });
+            scope.spawn(|| {
+                for _ in 0..rounds {
+                    let world = world.lock_shared();
+                    let moving = world.query::<(&Position, &Velocity)>();
+                    for (_, position, velocity) in moving.iter() {
+                        assert_eq!(position.0, velocity.0, "queried a torn entity");
+                    }
+                }
+            });
+        }
+    });
+
+    let world = world.lock_shared();
+    assert!(world.query::<&Position>().is_empty(), "a removal was lost");
+    assert!(world.query::<&Velocity>().is_empty(), "a removal was lost");
+    assert_eq!(
+        world.removed::<Position>().len(),
+        tasks as usize * rounds as usize
+    );
+}
+
+#[test]
+fn concurrent_resource_writers_against_readers() {
+    struct Pair(u32, u32);
+    struct Reads(u32);
+
+    let (tasks, rounds) = if cfg!(miri) { (2, 4) } else { (8, 500) };
+
+    let mut world = World::new();
+    world.create_resource(Pair(0, 0));
+    world.create_resource(Reads(0));
+
+    std::thread::scope(|scope| {
+        for _ in 0..tasks {
+            scope.spawn(|| {
+                for _ in 0..rounds {
+                    let mut pair = world.resource_mut::<Pair>().unwrap();
+                    pair.0 += 1;
+                    std::thread::yield_now();
+                    pair.1 += 1;
+                }
+            });
+            scope.spawn(|| {
+                for _ in 0..rounds {
+                    let pair = world.resource::<Pair>().unwrap();
+                    assert_eq!(pair.0, pair.1, "read a half written resource");
+                    drop(pair);
+                    world.resource_mut::<Reads>().unwrap().0 += 1;
+                }
+            });
+        }
+    });
+
+    let pair = world.resource::<Pair>().unwrap();
+    assert_eq!((pair.0, pair.1), (tasks * rounds, tasks * rounds), "lost a write");
+    assert_eq!(world.resource::<Reads>().unwrap().0, tasks * rounds);
+}
+
+#[test]
+fn retain_changes_nothing_and_skips_hooks() {
+    use crate::query::Changed;
+    use crate::work::Work;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct Tile(u32);
+
+    static CHANGED: AtomicUsize = AtomicUsize::new(0);
+    static REMOVED: AtomicUsize = AtomicUsize::new(0);
+    static HOOKS: AtomicUsize = AtomicUsize::new(0);
+
+    fn detect(world: &World) {
+        let changed = world.query_filtered::<&Tile, Changed<Tile>>().len();
+        CHANGED.store(changed, Ordering::SeqCst);
+        REMOVED.store(world.removed::<Tile>().len(), Ordering::SeqCst);
+    }
+
+    let mut world = World::new();
+    world.on_remove::<Tile, _>(|_, _| {
+        HOOKS.fetch_add(1, Ordering::SeqCst);
+    });
+    for i in 0..4 {
+        let entity = world.create_entity();
+        world.insert(&entity, Tile(i));
+    }
+
+    let work = Work::new().add_system(detect);
+    work.run(&world);
+    assert_eq!(CHANGED.load(Ordering::SeqCst), 4);
+
+    world
+        .components_mut::<Tile>()
+        .unwrap()
+        .retain(|_, tile| tile.0 % 2 == 0);
+    work.run(&world);
+    assert_eq!(CHANGED.load(Ordering::SeqCst), 0, "kept components were marked changed");
+    assert_eq!(REMOVED.load(Ordering::SeqCst), 2);
+    assert_eq!(HOOKS.load(Ordering::SeqCst), 0, "retain ran on_remove hooks");
+    assert_eq!(world.components::<Tile>().unwrap().len(), 2);
+}